@@ -0,0 +1,545 @@
+use std::ops::Range;
+
+use gfx_hal::{buffer, image, format, memory, Backend};
+use gfx_hal::command::RawCommandBuffer;
+use gfx_hal::pso::PipelineStage;
+use gfx_hal::queue::QueueFamilyId;
+
+use crate::graph::{ImageCreateInfo, ImageRead, ImageWrite, BufferCreateInfo, QueueType, internal::RenderPassKind};
+use crate::compiled::{Imports, resources::PhysicalResources};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct ImageState {
+    pub access: image::Access,
+    pub layout: image::Layout,
+    pub stages: PipelineStage,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct BufferState {
+    pub access: buffer::Access,
+    pub stages: PipelineStage,
+}
+
+// The submissions of a frame, in order: one on the graphics queue before the passes, one per pass, and
+// one on the graphics queue after them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum SyncPoint {
+    Begin,
+    Pass(usize),
+    End,
+}
+
+// The queue a resource is owned by, and the submission that last used it there.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) struct Owner {
+    pub queue: QueueType,
+    pub point: SyncPoint,
+}
+
+// How a barrier takes part in moving a resource between queues. Queue types are only mapped to queues by
+// the renderer, so both halves of every move are compiled and the renderer drops those it doesn't need.
+#[derive(Clone, PartialEq, Debug)]
+pub(crate) enum Transfer {
+    None,
+    // Recorded on the queue the resource leaves, after its last use there.
+    Release(Range<QueueType>),
+    // Recorded on the queue the resource moves to, which waits for the other queue first. Discarded
+    // contents are not worth moving ownership of.
+    Acquire { queues: Range<QueueType>, contents: bool },
+}
+
+pub(crate) struct ImageBarrier {
+    pub image: usize,
+    pub states: Range<image::State>,
+    pub stages: Range<PipelineStage>,
+    pub range: image::SubresourceRange,
+    pub transfer: Transfer,
+}
+
+pub(crate) struct BufferBarrier {
+    pub buffer: usize,
+    pub states: Range<buffer::State>,
+    pub stages: Range<PipelineStage>,
+    pub range: Range<u64>,
+    pub transfer: Transfer,
+}
+
+pub(crate) struct Barriers {
+    pub images: Vec<ImageBarrier>,
+    pub buffers: Vec<BufferBarrier>,
+}
+
+impl Barriers {
+    pub(crate) fn new() -> Self {
+        Barriers {
+            images: Vec::new(),
+            buffers: Vec::new(),
+        }
+    }
+
+    // Returns the number of barriers recorded, which leaves out the releases between queue types that
+    // `queues` runs on the same queue.
+    pub(crate) fn record<B: Backend>(
+        &self,
+        buffer: &mut B::CommandBuffer,
+        resources: &PhysicalResources<B>,
+        frame: usize,
+        imports: &Imports<B>,
+        queues: &ResolvedQueues,
+    ) -> usize {
+        let mut stages = PipelineStage::empty()..PipelineStage::empty();
+        let mut barriers = Vec::new();
+        for b in &self.images {
+            let resolved = match queues.resolve(&b.transfer, &b.stages) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            stages.start |= resolved.stages.start;
+            stages.end |= resolved.stages.end;
+            let access = if resolved.source { b.states.start.0 } else { image::Access::empty() };
+            barriers.push(memory::Barrier::Image {
+                states: (access, b.states.start.1)..b.states.end,
+                target: resources.get_image(b.image, frame, imports),
+                families: resolved.families,
+                range: b.range.clone(),
+            });
+        }
+        for b in &self.buffers {
+            let resolved = match queues.resolve(&b.transfer, &b.stages) {
+                Some(resolved) => resolved,
+                None => continue,
+            };
+            stages.start |= resolved.stages.start;
+            stages.end |= resolved.stages.end;
+            let access = if resolved.source { b.states.start } else { buffer::Access::empty() };
+            barriers.push(memory::Barrier::Buffer {
+                states: access..b.states.end,
+                target: resources.get_buffer(b.buffer, frame, imports),
+                families: resolved.families,
+                range: Some(b.range.start)..Some(b.range.end),
+            });
+        }
+        if !barriers.is_empty() {
+            buffer.pipeline_barrier(stages, memory::Dependencies::empty(), &barriers);
+        }
+        barriers.len()
+    }
+}
+
+// The queue and queue family each queue type runs on in a renderer.
+#[derive(Clone, Debug)]
+pub(crate) struct ResolvedQueues {
+    pub graphics: (QueueType, QueueFamilyId),
+    pub compute: (QueueType, QueueFamilyId),
+    pub transfer: (QueueType, QueueFamilyId),
+}
+
+// A barrier as recorded on the queues a renderer runs on.
+struct Resolved {
+    stages: Range<PipelineStage>,
+    // Whether the barrier covers the accesses before it, which an acquiring queue leaves to the semaphore
+    // it waits on.
+    source: bool,
+    families: Option<Range<QueueFamilyId>>,
+}
+
+impl ResolvedQueues {
+    pub(crate) fn get(&self, queue: QueueType) -> (QueueType, QueueFamilyId) {
+        match queue {
+            QueueType::Graphics => self.graphics,
+            QueueType::Compute => self.compute,
+            QueueType::Transfer => self.transfer,
+        }
+    }
+
+    fn resolve(&self, transfer: &Transfer, stages: &Range<PipelineStage>) -> Option<Resolved> {
+        let local = Resolved { stages: stages.clone(), source: true, families: None };
+        let (queues, contents) = match *transfer {
+            Transfer::None => return Some(local),
+            Transfer::Release(ref queues) => (queues, true),
+            Transfer::Acquire { ref queues, contents } => (queues, contents),
+        };
+        let (from, to) = (self.get(queues.start), self.get(queues.end));
+        let families = if contents && from.1 != to.1 { Some(from.1..to.1) } else { None };
+        match *transfer {
+            // Queue types sharing a queue leave the acquiring barrier to do the work of an ordinary one.
+            _ if from.0 == to.0 => match *transfer {
+                Transfer::Release(_) => None,
+                _ => Some(local),
+            },
+            Transfer::Release(_) => families.map(|families| Resolved { families: Some(families), ..local }),
+            _ => Some(Resolved { stages: queue_stages(to.0)..stages.end, source: false, families: families }),
+        }
+    }
+}
+
+// Follows the state of every physical resource through the schedule, emitting a barrier whenever an
+// access needs a different layout or either side of it writes. Images are followed per mip level and
+// layer, so accesses to different parts of an image don't wait on each other.
+// A resource is owned by one queue at a time, and using it on another moves it over: it is released after
+// its last use on the old queue, and the new queue waits for that before acquiring it.
+pub(crate) struct StateTracker {
+    images: Vec<SubresourceStates>,
+    // The state of each byte range of a buffer, in order and covering all of it.
+    buffers: Vec<Vec<(Range<u64>, Tracked<BufferState>)>>,
+    releases: Vec<(SyncPoint, Barriers)>,
+    // Pairs of submissions on different queues, the second of which has to wait for the first.
+    syncs: Vec<(SyncPoint, SyncPoint)>,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+struct Tracked<S> {
+    state: S,
+    // None until a queue uses the resource in the frame, if it isn't owned from the start.
+    owner: Option<Owner>,
+}
+
+struct SubresourceStates {
+    layers: image::Layer,
+    // Indexed by level, then layer.
+    states: Vec<Tracked<ImageState>>,
+}
+
+impl StateTracker {
+    pub(crate) fn new(images: Vec<(image::Layout, ImageCreateInfo, Option<Owner>)>, buffers: Vec<(u64, Option<Owner>)>) -> Self {
+        StateTracker {
+            images: images.into_iter().map(|(layout, info, owner)| {
+                let layers = info.kind.num_layers();
+                let state = ImageState {
+                    access: image::Access::empty(),
+                    layout: layout,
+                    stages: PipelineStage::TOP_OF_PIPE,
+                };
+                SubresourceStates {
+                    layers: layers,
+                    states: vec![Tracked { state: state, owner: owner }; info.mip_levels as usize * layers as usize],
+                }
+            }).collect(),
+            buffers: buffers.into_iter().map(|(size, owner)| {
+                let state = BufferState { access: buffer::Access::empty(), stages: PipelineStage::TOP_OF_PIPE };
+                vec![(0..size, Tracked { state: state, owner: owner })]
+            }).collect(),
+            releases: Vec::new(),
+            syncs: Vec::new(),
+        }
+    }
+
+    // `discard` marks the first write to a resource this frame, whose previous contents are not needed.
+    pub(crate) fn transition_image(
+        &mut self,
+        barriers: &mut Barriers,
+        image: usize,
+        new: ImageState,
+        range: image::SubresourceRange,
+        discard: bool,
+        user: Owner,
+    ) {
+        let subresources = &mut self.images[image];
+        let mut runs: Vec<(Tracked<ImageState>, Range<image::Level>, Range<image::Layer>)> = Vec::new();
+        for level in range.levels.clone() {
+            for layer in range.layers.clone() {
+                let tracked = &mut subresources.states[level as usize * subresources.layers as usize + layer as usize];
+                let old = *tracked;
+                let owned = old.owner.map_or(true, |owner| owner.queue == user.queue);
+                if owned && old.state.layout == new.layout && !is_image_write(old.state.access) && !is_image_write(new.access) {
+                    tracked.state.access |= new.access;
+                    tracked.state.stages |= new.stages;
+                    tracked.owner = Some(user);
+                    continue
+                }
+                *tracked = Tracked { state: new, owner: Some(user) };
+
+                // Subresources that were in the same state share a barrier, one per run of layers in a level
+                // and then merged across levels with the same runs.
+                match runs.last_mut() {
+                    Some(&mut (ref s, ref levels, ref mut layers)) if *s == old && levels.start == level && layers.end == layer => {
+                        layers.end += 1;
+                        continue
+                    },
+                    _ => (),
+                }
+                runs.push((old, level..level + 1, layer..layer + 1));
+            }
+        }
+
+        let mut merged: Vec<(Tracked<ImageState>, Range<image::Level>, Range<image::Layer>)> = Vec::new();
+        for (old, levels, layers) in runs {
+            match merged.last_mut() {
+                Some(&mut (ref s, ref mut l, ref ls)) if *s == old && *ls == layers && l.end == levels.start => {
+                    l.end = levels.end;
+                    continue
+                },
+                _ => (),
+            }
+            merged.push((old, levels, layers));
+        }
+
+        for (old, levels, layers) in merged {
+            let range = image::SubresourceRange { aspects: range.aspects, levels: levels, layers: layers };
+            let layout = if discard { image::Layout::Undefined } else { old.state.layout };
+            let transfer = self.transfer(old.owner, user, discard, |releases, transfer| releases.images.push(ImageBarrier {
+                image: image,
+                states: (old.state.access, layout)..(image::Access::empty(), new.layout),
+                stages: old.state.stages..PipelineStage::BOTTOM_OF_PIPE,
+                range: range.clone(),
+                transfer: transfer,
+            }));
+            barriers.images.push(ImageBarrier {
+                image: image,
+                states: (old.state.access, layout)..(new.access, new.layout),
+                stages: old.state.stages..new.stages,
+                range: range,
+                transfer: transfer,
+            });
+        }
+    }
+
+    // Only the bytes in `range` are transitioned, so accesses to other parts of the buffer keep their state.
+    pub(crate) fn transition_buffer(
+        &mut self,
+        barriers: &mut Barriers,
+        buffer: usize,
+        new: BufferState,
+        range: Range<u64>,
+        discard: bool,
+        user: Owner,
+    ) {
+        let mut changed = Vec::new();
+        {
+            let segments = &mut self.buffers[buffer];
+            split_segments(segments, range.start);
+            split_segments(segments, range.end);
+
+            for &mut (ref bytes, ref mut tracked) in segments.iter_mut() {
+                if bytes.end <= range.start || bytes.start >= range.end {
+                    continue
+                }
+                let old = *tracked;
+                let owned = old.owner.map_or(true, |owner| owner.queue == user.queue);
+                if owned && !is_buffer_write(old.state.access) && !is_buffer_write(new.access) {
+                    tracked.state.access |= new.access;
+                    tracked.state.stages |= new.stages;
+                    tracked.owner = Some(user);
+                    continue
+                }
+                changed.push((bytes.clone(), old));
+                *tracked = Tracked { state: new, owner: Some(user) };
+            }
+
+            segments.dedup_by(|next, previous| {
+                if next.1 != previous.1 {
+                    return false
+                }
+                previous.0.end = next.0.end;
+                true
+            });
+        }
+
+        for (bytes, old) in changed {
+            let transfer = self.transfer(old.owner, user, discard, |releases, transfer| releases.buffers.push(BufferBarrier {
+                buffer: buffer,
+                states: old.state.access..buffer::Access::empty(),
+                stages: old.state.stages..PipelineStage::BOTTOM_OF_PIPE,
+                range: bytes.clone(),
+                transfer: transfer,
+            }));
+            barriers.buffers.push(BufferBarrier {
+                buffer: buffer,
+                states: old.state.access..new.access,
+                stages: old.state.stages..new.stages,
+                range: bytes,
+                transfer: transfer,
+            });
+        }
+    }
+
+    // The barriers releasing resources after each submission, and the submissions that have to wait for
+    // others.
+    pub(crate) fn finish(self) -> (Vec<(SyncPoint, Barriers)>, Vec<(SyncPoint, SyncPoint)>) {
+        (self.releases, self.syncs)
+    }
+
+    // Moves a resource from `owner` to the queue of `user` if they differ, passing `release` the barriers
+    // recorded where the owner last used it. Returns how the barrier recorded for `user` takes part.
+    fn transfer<F: FnOnce(&mut Barriers, Transfer)>(
+        &mut self,
+        owner: Option<Owner>,
+        user: Owner,
+        discard: bool,
+        release: F,
+    ) -> Transfer {
+        let owner = match owner {
+            Some(owner) if owner.queue != user.queue => owner,
+            _ => return Transfer::None,
+        };
+        if !self.syncs.contains(&(owner.point, user.point)) {
+            self.syncs.push((owner.point, user.point));
+        }
+        let queues = owner.queue..user.queue;
+        if !discard {
+            let index = match self.releases.iter().position(|&(point, _)| point == owner.point) {
+                Some(index) => index,
+                None => {
+                    self.releases.push((owner.point, Barriers::new()));
+                    self.releases.len() - 1
+                },
+            };
+            release(&mut self.releases[index].1, Transfer::Release(queues.clone()));
+        }
+        Transfer::Acquire { queues: queues, contents: !discard }
+    }
+}
+
+// Splits the segment containing `offset` in two, so a segment starts there.
+fn split_segments(segments: &mut Vec<(Range<u64>, Tracked<BufferState>)>, offset: u64) {
+    if let Some(index) = segments.iter().position(|&(ref bytes, _)| bytes.start < offset && offset < bytes.end) {
+        let (bytes, state) = segments[index].clone();
+        segments[index].0.end = offset;
+        segments.insert(index + 1, (offset..bytes.end, state));
+    }
+}
+
+fn is_image_write(access: image::Access) -> bool {
+    access.intersects(
+        image::Access::SHADER_WRITE | image::Access::COLOR_ATTACHMENT_WRITE |
+        image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE | image::Access::TRANSFER_WRITE |
+        image::Access::HOST_WRITE | image::Access::MEMORY_WRITE
+    )
+}
+
+fn is_buffer_write(access: buffer::Access) -> bool {
+    access.intersects(
+        buffer::Access::SHADER_WRITE | buffer::Access::TRANSFER_WRITE |
+        buffer::Access::HOST_WRITE | buffer::Access::MEMORY_WRITE
+    )
+}
+
+// Every stage work on `queue` may run in, for waiting on other queues without knowing what for.
+pub(crate) fn queue_stages(queue: QueueType) -> PipelineStage {
+    match queue {
+        QueueType::Graphics => {
+            PipelineStage::DRAW_INDIRECT | PipelineStage::VERTEX_INPUT | PipelineStage::VERTEX_SHADER |
+            PipelineStage::FRAGMENT_SHADER | PipelineStage::EARLY_FRAGMENT_TESTS | PipelineStage::LATE_FRAGMENT_TESTS |
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::COMPUTE_SHADER | PipelineStage::TRANSFER
+        },
+        QueueType::Compute => PipelineStage::DRAW_INDIRECT | PipelineStage::COMPUTE_SHADER | PipelineStage::TRANSFER,
+        QueueType::Transfer => PipelineStage::TRANSFER,
+    }
+}
+
+// History resources are left readable between frames, ready for the next frame to read the previous version.
+pub(crate) fn history_image_state() -> ImageState {
+    ImageState {
+        access: image::Access::SHADER_READ | image::Access::TRANSFER_READ,
+        layout: image::Layout::ShaderReadOnlyOptimal,
+        stages: PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER |
+            PipelineStage::COMPUTE_SHADER | PipelineStage::TRANSFER,
+    }
+}
+
+pub(crate) fn history_buffer_state() -> BufferState {
+    BufferState {
+        access: buffer::Access::SHADER_READ | buffer::Access::TRANSFER_READ,
+        stages: PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER |
+            PipelineStage::COMPUTE_SHADER | PipelineStage::TRANSFER,
+    }
+}
+
+pub(crate) fn full_range(info: &ImageCreateInfo) -> image::SubresourceRange {
+    image::SubresourceRange {
+        aspects: info.format.surface_desc().aspects,
+        levels: 0..info.mip_levels,
+        layers: 0..info.kind.num_layers(),
+    }
+}
+
+pub(crate) fn is_depth(info: &ImageCreateInfo) -> bool {
+    info.format.surface_desc().aspects.contains(format::Aspects::DEPTH)
+}
+
+pub(crate) fn image_read_state<B: Backend, D>(kind: &RenderPassKind<B, D>, read: &ImageRead) -> ImageState {
+    let (access, layout, stages) = match (kind, read) {
+        (_, ImageRead::Attachment(_)) => {
+            (image::Access::INPUT_ATTACHMENT_READ, image::Layout::ShaderReadOnlyOptimal, PipelineStage::FRAGMENT_SHADER)
+        },
+        (RenderPassKind::Transfer(_), ImageRead::Transfer) => {
+            (image::Access::TRANSFER_READ, image::Layout::TransferSrcOptimal, PipelineStage::TRANSFER)
+        },
+        (RenderPassKind::Compute(_), ImageRead::Transfer) => {
+            (image::Access::SHADER_READ, image::Layout::ShaderReadOnlyOptimal, PipelineStage::COMPUTE_SHADER)
+        },
+        (_, ImageRead::Transfer) => {
+            (image::Access::SHADER_READ, image::Layout::ShaderReadOnlyOptimal, PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER)
+        },
+    };
+    ImageState { access: access, layout: layout, stages: stages }
+}
+
+pub(crate) fn image_write_state<B: Backend, D>(kind: &RenderPassKind<B, D>, write: &ImageWrite, info: &ImageCreateInfo) -> ImageState {
+    let (access, layout, stages) = match (kind, write) {
+        (_, ImageWrite::Attachment(..)) if is_depth(info) => (
+            image::Access::DEPTH_STENCIL_ATTACHMENT_READ | image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            image::Layout::DepthStencilAttachmentOptimal,
+            PipelineStage::EARLY_FRAGMENT_TESTS | PipelineStage::LATE_FRAGMENT_TESTS,
+        ),
+        (_, ImageWrite::Attachment(..)) => (
+            image::Access::COLOR_ATTACHMENT_READ | image::Access::COLOR_ATTACHMENT_WRITE,
+            image::Layout::ColorAttachmentOptimal,
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        ),
+        (RenderPassKind::Transfer(_), _) => {
+            (image::Access::TRANSFER_WRITE, image::Layout::TransferDstOptimal, PipelineStage::TRANSFER)
+        },
+        (RenderPassKind::Compute(_), _) => {
+            (image::Access::SHADER_WRITE, image::Layout::General, PipelineStage::COMPUTE_SHADER)
+        },
+        (_, _) => {
+            (image::Access::SHADER_WRITE, image::Layout::General, PipelineStage::FRAGMENT_SHADER)
+        },
+    };
+    ImageState { access: access, layout: layout, stages: stages }
+}
+
+pub(crate) fn buffer_read_state<B: Backend, D>(kind: &RenderPassKind<B, D>, info: &BufferCreateInfo) -> BufferState {
+    match kind {
+        RenderPassKind::Transfer(_) => BufferState {
+            access: buffer::Access::TRANSFER_READ,
+            stages: PipelineStage::TRANSFER,
+        },
+        RenderPassKind::Compute(_) => BufferState {
+            access: buffer::Access::SHADER_READ,
+            stages: PipelineStage::COMPUTE_SHADER,
+        },
+        _ => {
+            let mut access = buffer::Access::empty();
+            if info.usage.contains(buffer::Usage::VERTEX) { access |= buffer::Access::VERTEX_BUFFER_READ }
+            if info.usage.contains(buffer::Usage::INDEX) { access |= buffer::Access::INDEX_BUFFER_READ }
+            if info.usage.contains(buffer::Usage::UNIFORM) { access |= buffer::Access::CONSTANT_BUFFER_READ }
+            if info.usage.contains(buffer::Usage::INDIRECT) { access |= buffer::Access::INDIRECT_COMMAND_READ }
+            if info.usage.contains(buffer::Usage::STORAGE) { access |= buffer::Access::SHADER_READ }
+            BufferState {
+                access: access,
+                stages: PipelineStage::DRAW_INDIRECT | PipelineStage::VERTEX_INPUT |
+                    PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER,
+            }
+        },
+    }
+}
+
+pub(crate) fn buffer_write_state<B: Backend, D>(kind: &RenderPassKind<B, D>) -> BufferState {
+    match kind {
+        RenderPassKind::Transfer(_) => BufferState {
+            access: buffer::Access::TRANSFER_WRITE,
+            stages: PipelineStage::TRANSFER,
+        },
+        RenderPassKind::Compute(_) => BufferState {
+            access: buffer::Access::SHADER_WRITE,
+            stages: PipelineStage::COMPUTE_SHADER,
+        },
+        _ => BufferState {
+            access: buffer::Access::SHADER_WRITE,
+            stages: PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER,
+        },
+    }
+}
\ No newline at end of file
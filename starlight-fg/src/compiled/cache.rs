@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use gfx_hal::{pass, Backend, Device, MemoryType};
+
+use crate::graph::{FrameGraph, Scheduler, InsertionOrder};
+use crate::compiled::{CompiledFrameGraph, CompileError};
+
+// Everything a render pass is created from: its attachments and the references its one subpass makes to
+// them.
+#[derive(Clone, PartialEq, Hash, Debug)]
+pub(crate) struct RenderPassKey {
+    pub attachments: Vec<pass::Attachment>,
+    pub colors: Vec<pass::AttachmentRef>,
+    pub depth_stencil: Option<pass::AttachmentRef>,
+    pub inputs: Vec<pass::AttachmentRef>,
+}
+
+// Render passes shared between compiled graphs, so compiling a graph again only creates the render passes
+// that changed. They are found by a hash of their key, then compared with it in full. A render pass is
+// destroyed by whichever of the cache and the graphs using it lets go of it last.
+pub struct RenderPassCache<'g, B: Backend> {
+    device: &'g B::Device,
+    render_passes: HashMap<u64, Vec<(RenderPassKey, Arc<B::RenderPass>)>>,
+}
+
+impl<'g, B: Backend> RenderPassCache<'g, B> {
+    pub fn new(device: &'g B::Device) -> Self {
+        RenderPassCache {
+            device: device,
+            render_passes: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get<F>(&mut self, key: RenderPassKey, create: F) -> Result<Arc<B::RenderPass>, CompileError>
+        where F: FnOnce(&RenderPassKey) -> Result<B::RenderPass, CompileError>
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let bucket = self.render_passes.entry(hasher.finish()).or_insert_with(Vec::new);
+        if let Some(&(_, ref render_pass)) = bucket.iter().find(|&&(ref cached, _)| *cached == key) {
+            return Ok(render_pass.clone())
+        }
+        let render_pass = Arc::new(create(&key)?);
+        bucket.push((key, render_pass.clone()));
+        Ok(render_pass)
+    }
+
+    // Destroys the render passes no compiled graph uses any more.
+    pub fn trim(&mut self) {
+        let device = self.device;
+        for bucket in self.render_passes.values_mut() {
+            let (unused, used) = bucket.drain(..)
+                .partition::<Vec<_>, _>(|&(_, ref render_pass)| Arc::strong_count(render_pass) == 1);
+            *bucket = used;
+            for (_, render_pass) in unused {
+                release_render_pass(device, render_pass);
+            }
+        }
+        self.render_passes.retain(|_, bucket| !bucket.is_empty());
+    }
+
+    pub fn len(&self) -> usize {
+        self.render_passes.values().map(Vec::len).sum()
+    }
+}
+
+impl<'g, B: Backend> Drop for RenderPassCache<'g, B> {
+    fn drop(&mut self) {
+        for (_, bucket) in self.render_passes.drain() {
+            for (_, render_pass) in bucket {
+                release_render_pass(self.device, render_pass);
+            }
+        }
+    }
+}
+
+pub(crate) fn release_render_pass<B: Backend>(device: &B::Device, render_pass: Arc<B::RenderPass>) {
+    if let Ok(render_pass) = Arc::try_unwrap(render_pass) {
+        device.destroy_render_pass(render_pass);
+    }
+}
+
+// Keeps the last compiled graph around, so that a graph rebuilt every frame is only compiled again when
+// its structure changes.
+pub struct GraphCache<'g, B: Backend, D = ()> {
+    device: &'g B::Device,
+    memory_types: Vec<MemoryType>,
+    frames_in_flight: usize,
+    scheduler: Box<dyn Scheduler + 'g>,
+    render_passes: RenderPassCache<'g, B>,
+    compiled: Option<CompiledFrameGraph<'g, B, D>>,
+}
+
+impl<'g, B: Backend, D> GraphCache<'g, B, D> {
+    pub fn new(device: &'g B::Device, memory_types: &[MemoryType], frames_in_flight: usize) -> Self {
+        Self::with_scheduler(device, memory_types, frames_in_flight, Box::new(InsertionOrder))
+    }
+
+    pub fn with_scheduler(
+        device: &'g B::Device,
+        memory_types: &[MemoryType],
+        frames_in_flight: usize,
+        scheduler: Box<dyn Scheduler + 'g>,
+    ) -> Self {
+        GraphCache {
+            device: device,
+            memory_types: memory_types.to_vec(),
+            frames_in_flight: frames_in_flight,
+            scheduler: scheduler,
+            render_passes: RenderPassCache::new(device),
+            compiled: None,
+        }
+    }
+
+    // Returns true if the graph had to be compiled again, in which case anything created from the
+    // previous compiled graph, such as a renderer, must be recreated. The old graph's resources may still
+    // be in use by frames in flight, so compiling again first waits for the device to go idle.
+    pub fn update(&mut self, graph: FrameGraph<'g, B, D>) -> Result<bool, CompileError> {
+        if let Some(ref mut compiled) = self.compiled {
+            if compiled.matches(&graph) {
+                compiled.rebind(graph);
+                return Ok(false)
+            }
+        }
+
+        // Free the old resources before allocating new ones. Render passes are kept until the new graph has
+        // had the chance to reuse them.
+        self.release()?;
+        let compiled = CompiledFrameGraph::with_cache(
+            self.device, &self.memory_types, graph, self.frames_in_flight, &*self.scheduler, &mut self.render_passes
+        );
+        self.render_passes.trim();
+        self.compiled = Some(compiled?);
+        Ok(true)
+    }
+
+    pub fn compiled(&self) -> Option<&CompiledFrameGraph<'g, B, D>> {
+        self.compiled.as_ref()
+    }
+
+    // Waits for the device to go idle, like `update`, before destroying the compiled graph.
+    pub fn clear(&mut self) -> Result<(), CompileError> {
+        self.release()?;
+        self.render_passes.trim();
+        Ok(())
+    }
+
+    fn release(&mut self) -> Result<(), CompileError> {
+        if self.compiled.is_some() {
+            self.device.wait_idle()?;
+            self.compiled = None;
+        }
+        Ok(())
+    }
+}
\ No newline at end of file
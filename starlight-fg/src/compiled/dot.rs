@@ -0,0 +1,31 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gfx_hal::Backend;
+
+use crate::graph::{to_dot, DotInfo, PassRef, BufferRef, ImageRef};
+use crate::compiled::CompiledFrameGraph;
+
+impl<'g, B: Backend, D> CompiledFrameGraph<'g, B, D> {
+    // Like `FrameGraph::to_dot`, but also shows where each pass was scheduled, greys out culled passes
+    // and marks the resources that share memory with others in red.
+    pub fn to_dot(&self) -> String {
+        let position = |pass: PassRef| self.passes().iter().position(|p| p.pass == pass);
+        let live = |pass: PassRef| position(pass).is_some();
+        let buffer = |buffer: BufferRef| self.resources().find_buffer(buffer)
+            .map(|index| (index, self.resources().is_aliased_buffer(index)));
+        let image = |image: ImageRef| self.resources().find_image(image)
+            .map(|index| (index, self.resources().is_aliased_image(index)));
+        to_dot(self.graph(), &DotInfo {
+            live: Some(&live),
+            position: Some(&position),
+            buffer: Some(&buffer),
+            image: Some(&image),
+        })
+    }
+
+    pub fn write_dot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_dot())
+    }
+}
\ No newline at end of file
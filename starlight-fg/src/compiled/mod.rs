@@ -0,0 +1,729 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use gfx_hal::{buffer, device, format, image, pass, Backend, Device, MemoryType};
+use gfx_hal::command::{ClearColor, ClearDepthStencil, ClearValue, ClearValueRaw};
+use gfx_hal::device::OutOfMemory;
+use gfx_hal::error::HostExecutionError;
+use gfx_hal::pso::{PipelineStage, Rect};
+
+use crate::context::ResourceHandles;
+use crate::serial::SamplerCache;
+use crate::graph::{
+    FrameGraph, GraphStructure, PassRef, BufferRef, ImageCreateInfo, ImageRef, ImageRead, ImageWrite, CullReason,
+    QueueTransfer, QueueType, Scheduler, InsertionOrder,
+    internal::{FrameGraphInternals, PassDependency, RenderPass, RenderPassKind},
+};
+
+mod barriers;
+mod cache;
+mod dot;
+mod resources;
+
+use self::barriers::*;
+use self::cache::{release_render_pass, RenderPassKey};
+use self::resources::{PhysicalResources, ViewKey};
+
+pub use self::cache::*;
+
+pub(crate) use self::barriers::{full_range, queue_stages, ResolvedQueues, SyncPoint};
+pub(crate) use self::resources::view_kind;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Imports<'a, B: Backend> {
+    images: HashMap<ImageRef, (&'a B::Image, u64)>,
+    buffers: HashMap<BufferRef, &'a B::Buffer>,
+}
+
+impl<'a, B: Backend> Imports<'a, B> {
+    pub fn new() -> Self {
+        Imports {
+            images: HashMap::new(),
+            buffers: HashMap::new(),
+        }
+    }
+
+    // `key` tells `handle` apart from the other images imported in its place on other frames, such as the
+    // rest of a swapchain. Renderers keep what they make of the image under it until it is evicted, so it
+    // can't be given to a different image before then.
+    pub fn insert_image(&mut self, image: ImageRef, handle: &'a B::Image, key: u64) {
+        self.images.insert(image, (handle, key));
+    }
+
+    pub fn insert_buffer(&mut self, buffer: BufferRef, handle: &'a B::Buffer) {
+        self.buffers.insert(buffer, handle);
+    }
+
+    pub(crate) fn get_image(&self, image: ImageRef) -> &'a B::Image {
+        self.images.get(&image).expect("Imported image was not provided!").0
+    }
+
+    pub(crate) fn image_key(&self, image: ImageRef) -> u64 {
+        self.images.get(&image).expect("Imported image was not provided!").1
+    }
+
+    pub(crate) fn get_buffer(&self, buffer: BufferRef) -> &'a B::Buffer {
+        self.buffers.get(&buffer).expect("Imported buffer was not provided!")
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    OutOfMemory(OutOfMemory),
+    NoMemoryType,
+    ImageCreation(image::CreationError),
+    BufferCreation(buffer::CreationError),
+    Allocation(device::AllocationError),
+    Bind(device::BindError),
+    ViewCreation(image::ViewError),
+    NoAttachmentView,
+    Idle(HostExecutionError),
+    Resource {
+        resource: String,
+        error: Box<CompileError>,
+    },
+    Pass {
+        pass: String,
+        error: Box<CompileError>,
+    },
+}
+
+impl From<OutOfMemory> for CompileError {
+    fn from(error: OutOfMemory) -> Self {
+        CompileError::OutOfMemory(error)
+    }
+}
+
+impl From<image::CreationError> for CompileError {
+    fn from(error: image::CreationError) -> Self {
+        CompileError::ImageCreation(error)
+    }
+}
+
+impl From<buffer::CreationError> for CompileError {
+    fn from(error: buffer::CreationError) -> Self {
+        CompileError::BufferCreation(error)
+    }
+}
+
+impl From<device::AllocationError> for CompileError {
+    fn from(error: device::AllocationError) -> Self {
+        CompileError::Allocation(error)
+    }
+}
+
+impl From<device::BindError> for CompileError {
+    fn from(error: device::BindError) -> Self {
+        CompileError::Bind(error)
+    }
+}
+
+impl From<image::ViewError> for CompileError {
+    fn from(error: image::ViewError) -> Self {
+        CompileError::ViewCreation(error)
+    }
+}
+
+impl From<HostExecutionError> for CompileError {
+    fn from(error: HostExecutionError) -> Self {
+        CompileError::Idle(error)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompileError::OutOfMemory(ref error) => write!(f, "Out of memory: {:?}", error),
+            CompileError::NoMemoryType => write!(f, "No device local memory type fits a transient resource"),
+            CompileError::ImageCreation(ref error) => write!(f, "Failed to create transient image: {:?}", error),
+            CompileError::BufferCreation(ref error) => write!(f, "Failed to create transient buffer: {:?}", error),
+            CompileError::Allocation(ref error) => write!(f, "Failed to allocate transient memory: {:?}", error),
+            CompileError::Bind(ref error) => write!(f, "Failed to bind transient memory: {:?}", error),
+            CompileError::ViewCreation(ref error) => write!(f, "Failed to create image view: {:?}", error),
+            CompileError::NoAttachmentView => write!(f, "Image is used as an attachment but has no attachment usage"),
+            CompileError::Idle(ref error) => write!(f, "Failed to wait for the device to go idle: {:?}", error),
+            CompileError::Resource { ref resource, ref error } => write!(f, "{}: {}", resource, error),
+            CompileError::Pass { ref pass, ref error } => write!(f, "{}: {}", pass, error),
+        }
+    }
+}
+
+impl Error for CompileError {}
+
+pub(crate) struct RenderAttachment {
+    pub image: usize,
+    pub view: usize,
+}
+
+pub(crate) struct CompiledRenderPass<B: Backend> {
+    pub handle: Arc<B::RenderPass>,
+    pub attachments: Vec<RenderAttachment>,
+    pub extent: image::Extent,
+    pub render_area: Rect,
+    pub clear_values: Vec<ClearValueRaw>,
+}
+
+pub(crate) struct CompiledPass<B: Backend> {
+    pub pass: PassRef,
+    pub pre_barriers: Barriers,
+    pub post_barriers: Barriers,
+    pub render_pass: Option<CompiledRenderPass<B>>,
+    // Each image used, with its physical index, layout and view, if it has one.
+    images: Vec<(ImageRef, usize, image::Layout, Option<usize>)>,
+    buffers: Vec<(BufferRef, usize)>,
+}
+
+// A frame graph together with everything needed to execute it: the schedule, physical resources, the
+// barriers between passes and their render passes. Only imported resources change from frame to frame.
+pub struct CompiledFrameGraph<'g, B: Backend, D = ()> {
+    device: &'g B::Device,
+    // Sets this compiled graph apart from every other, even one compiled from the same graph.
+    id: usize,
+    graph: FrameGraph<'g, B, D>,
+    structure: GraphStructure,
+    hash: u64,
+    schedule: Vec<PassRef>,
+    culled: Vec<(PassRef, CullReason)>,
+    queue_transfers: Vec<QueueTransfer>,
+    passes: Vec<CompiledPass<B>>,
+    frames_in_flight: usize,
+    history_barriers: Barriers,
+    begin_barriers: Barriers,
+    end_barriers: Barriers,
+    syncs: Vec<(SyncPoint, SyncPoint)>,
+    resources: PhysicalResources<B>,
+}
+
+impl<'g, B: Backend, D> CompiledFrameGraph<'g, B, D> {
+    // Every transient resource is created once for each of the `frames_in_flight` frames the GPU may be
+    // working on at the same time.
+    pub fn new(
+        device: &'g B::Device,
+        memory_types: &[MemoryType],
+        graph: FrameGraph<'g, B, D>,
+        frames_in_flight: usize,
+    ) -> Result<Self, CompileError> {
+        Self::with_scheduler(device, memory_types, graph, frames_in_flight, &InsertionOrder)
+    }
+
+    pub fn with_scheduler(
+        device: &'g B::Device,
+        memory_types: &[MemoryType],
+        graph: FrameGraph<'g, B, D>,
+        frames_in_flight: usize,
+        scheduler: &dyn Scheduler,
+    ) -> Result<Self, CompileError> {
+        Self::with_cache(device, memory_types, graph, frames_in_flight, scheduler, &mut RenderPassCache::new(device))
+    }
+
+    // Takes render passes from `render_passes` where it has them, adding those it doesn't.
+    pub fn with_cache(
+        device: &'g B::Device,
+        memory_types: &[MemoryType],
+        graph: FrameGraph<'g, B, D>,
+        frames_in_flight: usize,
+        scheduler: &dyn Scheduler,
+        render_passes: &mut RenderPassCache<'g, B>,
+    ) -> Result<Self, CompileError> {
+        assert!(frames_in_flight > 0, "At least one frame must be in flight!");
+        let (schedule, culled, queue_transfers) = {
+            let compiled = graph.compile_with(scheduler);
+            (compiled.schedule().to_vec(), compiled.culled().to_vec(), compiled.queue_transfers().to_vec())
+        };
+        let mut resources = PhysicalResources::new(device, memory_types, &graph, &schedule, frames_in_flight)?;
+        let mut passes = Vec::new();
+        let created = Self::create_passes(device, &graph, &schedule, &mut resources, render_passes, &mut passes);
+        let (begin_barriers, end_barriers, syncs) = match created {
+            Ok(created) => created,
+            Err(error) => {
+                for pass in passes {
+                    if let Some(render_pass) = pass.render_pass {
+                        release_render_pass(device, render_pass.handle);
+                    }
+                }
+                resources.destroy(device);
+                return Err(error)
+            },
+        };
+        let structure = graph.structure();
+        let history_barriers = Self::create_history_barriers(&graph, &resources);
+        Ok(CompiledFrameGraph {
+            device: device,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            hash: structure.digest(),
+            structure: structure,
+            graph: graph,
+            schedule: schedule,
+            culled: culled,
+            queue_transfers: queue_transfers,
+            passes: passes,
+            frames_in_flight: frames_in_flight,
+            history_barriers: history_barriers,
+            begin_barriers: begin_barriers,
+            end_barriers: end_barriers,
+            syncs: syncs,
+            resources: resources,
+        })
+    }
+
+    pub fn graph(&self) -> &FrameGraph<'g, B, D> {
+        &self.graph
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn structure(&self) -> &GraphStructure {
+        &self.structure
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        self.hash
+    }
+
+    // Whether `graph` could be rebound in place of the compiled one. Hashes are compared first, and only
+    // equal ones compared in full.
+    pub fn matches(&self, graph: &FrameGraph<'g, B, D>) -> bool {
+        let structure = graph.structure();
+        structure.digest() == self.hash && structure == self.structure
+    }
+
+    // Swaps in a newly built graph with the same structure as the compiled one, keeping everything
+    // derived from it. Only the pass callbacks change.
+    pub fn rebind(&mut self, graph: FrameGraph<'g, B, D>) {
+        assert!(self.matches(&graph), "Graph structure differs from the compiled graph!");
+        self.graph = graph;
+    }
+
+    pub fn schedule(&self) -> &[PassRef] {
+        &self.schedule
+    }
+
+    // The passes left out of the schedule because nothing needed them, and why.
+    pub fn culled(&self) -> &[(PassRef, CullReason)] {
+        &self.culled
+    }
+
+    pub fn queue_transfers(&self) -> &[QueueTransfer] {
+        &self.queue_transfers
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight
+    }
+
+    pub(crate) fn passes(&self) -> &[CompiledPass<B>] {
+        &self.passes
+    }
+
+    pub(crate) fn resources(&self) -> &PhysicalResources<B> {
+        &self.resources
+    }
+
+    // Brings the previous versions of history images into a readable layout on the first frame, before
+    // anything has been written to them.
+    pub(crate) fn history_barriers(&self) -> &Barriers {
+        &self.history_barriers
+    }
+
+    // Releases the resources the graphics queue owns when a frame begins to the queues that use them first.
+    pub(crate) fn begin_barriers(&self) -> &Barriers {
+        &self.begin_barriers
+    }
+
+    // Hands imported and history resources back to the graphics queue once every pass is done with them.
+    pub(crate) fn end_barriers(&self) -> &Barriers {
+        &self.end_barriers
+    }
+
+    // The submissions on different queues that have to wait for one another to move resources between them.
+    pub(crate) fn syncs(&self) -> &[(SyncPoint, SyncPoint)] {
+        &self.syncs
+    }
+
+    // `frame` counts every frame rendered since the graph was compiled.
+    // `import_views` holds the views of imported images the renderer has for this frame, along with the
+    // index of the view each one stands in for.
+    pub(crate) fn resource_handles<'a>(
+        &'a self,
+        pass: &CompiledPass<B>,
+        frame: usize,
+        imports: &Imports<'a, B>,
+        import_views: &'a [(usize, &'a B::ImageView)],
+        samplers: &'a SamplerCache<'a, B>,
+    ) -> ResourceHandles<'a, B> {
+        let mut handles = ResourceHandles::new(frame % self.frames_in_flight, frame > 0, samplers);
+        for &(image, index, layout, view) in &pass.images {
+            let view = view.map(|view| self.get_view(view, frame, import_views));
+            handles.insert_image(image, self.resources.get_image(index, frame, imports), layout, view);
+        }
+        for &(buffer, index) in &pass.buffers {
+            handles.insert_buffer(buffer, self.resources.get_buffer(index, frame, imports));
+        }
+        handles
+    }
+
+    pub(crate) fn get_view<'a>(&'a self, view: usize, frame: usize, import_views: &'a [(usize, &'a B::ImageView)]) -> &'a B::ImageView {
+        self.resources.get_view(view, frame)
+            .unwrap_or_else(|| import_views.iter().find(|&&(v, _)| v == view).expect("Missing view of imported image!").1)
+    }
+}
+
+impl<'g, B: Backend, D> CompiledFrameGraph<'g, B, D> {
+    fn create_passes(
+        device: &B::Device,
+        graph: &FrameGraph<'g, B, D>,
+        schedule: &[PassRef],
+        resources: &mut PhysicalResources<B>,
+        render_passes: &mut RenderPassCache<'g, B>,
+        passes: &mut Vec<CompiledPass<B>>,
+    ) -> Result<(Barriers, Barriers, Vec<(SyncPoint, SyncPoint)>), CompileError> {
+        // Imported and history resources outlive the frame, which they begin and end owned by the graphics queue.
+        let home = Some(Owner { queue: QueueType::Graphics, point: SyncPoint::Begin });
+        let initial_images = (0..resources.image_count()).map(|index| {
+            let image = resources.physical_image(index);
+            let (layout, owner) = match graph.get_image(image.root).write_type {
+                ImageWrite::Import(layout) => (layout, home),
+                ImageWrite::History if image.root.1 == 0 => (history_image_state().layout, home),
+                ImageWrite::History => (image::Layout::Undefined, home),
+                _ => (image::Layout::Undefined, None),
+            };
+            (layout, image.info, owner)
+        }).collect::<Vec<_>>();
+        let initial_buffers = (0..resources.buffer_count()).map(|index| {
+            let buffer = resources.physical_buffer(index);
+            let owner = match graph.get_pass(buffer.root.0).kind {
+                RenderPassKind::Import | RenderPassKind::History => home,
+                _ => None,
+            };
+            (buffer.info.size, owner)
+        }).collect();
+        let mut tracker = StateTracker::new(initial_images, initial_buffers);
+        let mut used_images = vec![false; resources.image_count()];
+        let mut used_buffers = vec![false; resources.buffer_count()];
+
+        for &pass_ref in schedule {
+            let pass = graph.get_pass(pass_ref);
+            match pass.kind {
+                RenderPassKind::Import | RenderPassKind::History => continue,
+                _ => (),
+            }
+
+            let user = Owner { queue: pass.queue, point: SyncPoint::Pass(passes.len()) };
+            let mut barriers = Barriers::new();
+            let mut images = Vec::new();
+            let mut buffers = Vec::new();
+            for (producer, dependency) in graph.dependencies(pass_ref) {
+                match *dependency {
+                    PassDependency::ReadImage(i, ref read, ref range) => {
+                        let image = ImageRef(producer, i);
+                        let index = resources.image_index(image);
+                        let state = image_read_state(&pass.kind, read);
+                        let info = resources.physical_image(index).info;
+                        let range = range.clone().unwrap_or_else(|| full_range(&info));
+                        let format = match *read {
+                            ImageRead::Attachment(attachment) => Some(attachment.format.unwrap_or(info.format)),
+                            ImageRead::Transfer => None,
+                        };
+                        let view = pass_view(device, graph, resources, pass, image, index, format, range.clone())?;
+                        tracker.transition_image(&mut barriers, index, state, range, false, user);
+                        images.push((image, index, state.layout, view));
+                        used_images[index] = true;
+                    },
+                    PassDependency::ReadBuffer(b, ref range) => {
+                        let buffer = BufferRef(producer, b);
+                        let index = resources.buffer_index(buffer);
+                        let info = &resources.physical_buffer(index).info;
+                        let state = buffer_read_state(&pass.kind, info);
+                        let range = range.clone().unwrap_or(0..info.size);
+                        tracker.transition_buffer(&mut barriers, index, state, range, false, user);
+                        buffers.push((buffer, index));
+                        used_buffers[index] = true;
+                    },
+                    PassDependency::CopyImage(..) | PassDependency::CopyBuffer(..) | PassDependency::Order => (),
+                }
+            }
+
+            for (i, resource) in pass.images.iter().enumerate() {
+                let image = ImageRef(pass_ref, i);
+                let index = resources.image_index(image);
+                let state = image_write_state(&pass.kind, &resource.write_type, &resource.info);
+                let discard = !is_version(graph, image) || match resource.write_type {
+                    ImageWrite::Attachment(_, clear) => clear,
+                    _ => false,
+                };
+                let range = resource.range.clone().unwrap_or_else(|| full_range(&resource.info));
+                let format = match resource.write_type {
+                    ImageWrite::Attachment(attachment, _) => Some(attachment.format.unwrap_or(resource.info.format)),
+                    _ => None,
+                };
+                let view = pass_view(device, graph, resources, pass, image, index, format, range.clone())?;
+                tracker.transition_image(&mut barriers, index, state, range, discard, user);
+                images.push((image, index, state.layout, view));
+                used_images[index] = true;
+            }
+
+            for (b, resource) in pass.buffers.iter().enumerate() {
+                let buffer = BufferRef(pass_ref, b);
+                let index = resources.buffer_index(buffer);
+                let range = resource.range.clone().unwrap_or(0..resource.info.size);
+                let discard = !is_buffer_version(graph, buffer);
+                tracker.transition_buffer(&mut barriers, index, buffer_write_state(&pass.kind), range, discard, user);
+                buffers.push((buffer, index));
+                used_buffers[index] = true;
+            }
+
+            let render_pass = match pass.kind {
+                RenderPassKind::Graphics(..) => {
+                    let render_pass = Self::create_render_pass(device, graph, pass_ref, resources, render_passes, &images);
+                    Some(render_pass.map_err(|error| CompileError::Pass {
+                        pass: graph.describe_pass(pass_ref),
+                        error: Box::new(error),
+                    })?)
+                },
+                _ => None,
+            };
+
+            passes.push(CompiledPass {
+                pass: pass_ref,
+                pre_barriers: barriers,
+                post_barriers: Barriers::new(),
+                render_pass: render_pass,
+                images: images,
+                buffers: buffers,
+            });
+        }
+
+        // Once the passes are done, imported images are handed back in the layout they were imported in,
+        // and history resources are made readable by the next frame.
+        let mut end = Barriers::new();
+        let user = Owner { queue: QueueType::Graphics, point: SyncPoint::End };
+        for (index, used) in used_images.into_iter().enumerate() {
+            let state = match (used, &graph.get_image(resources.physical_image(index).root).write_type) {
+                (true, ImageWrite::Import(layout)) => {
+                    ImageState { access: image::Access::empty(), layout: *layout, stages: PipelineStage::BOTTOM_OF_PIPE }
+                },
+                (true, ImageWrite::History) => history_image_state(),
+                _ => continue,
+            };
+            let range = full_range(&resources.physical_image(index).info);
+            tracker.transition_image(&mut end, index, state, range, false, user);
+        }
+        for (index, used) in used_buffers.into_iter().enumerate() {
+            let state = match (used, &graph.get_pass(resources.physical_buffer(index).root.0).kind) {
+                (true, RenderPassKind::Import) => {
+                    BufferState { access: buffer::Access::empty(), stages: PipelineStage::BOTTOM_OF_PIPE }
+                },
+                (true, RenderPassKind::History) => history_buffer_state(),
+                _ => continue,
+            };
+            let range = 0..resources.physical_buffer(index).info.size;
+            tracker.transition_buffer(&mut end, index, state, range, false, user);
+        }
+
+        let (releases, syncs) = tracker.finish();
+        let mut begin = Barriers::new();
+        for (point, releases) in releases {
+            match point {
+                SyncPoint::Begin => begin = releases,
+                SyncPoint::Pass(position) => passes[position].post_barriers = releases,
+                SyncPoint::End => unreachable!(),
+            }
+        }
+        Ok((begin, end, syncs))
+    }
+
+    fn create_history_barriers(graph: &FrameGraph<'g, B, D>, resources: &PhysicalResources<B>) -> Barriers {
+        let mut barriers = Barriers::new();
+        let state = history_image_state();
+        for index in 0..resources.image_count() {
+            let image = resources.physical_image(index);
+            if let (ImageWrite::History, 0) = (&graph.get_image(image.root).write_type, image.root.1) {
+                barriers.images.push(ImageBarrier {
+                    image: index,
+                    states: (image::Access::empty(), image::Layout::Undefined)..(state.access, state.layout),
+                    stages: PipelineStage::TOP_OF_PIPE..state.stages,
+                    range: full_range(&image.info),
+                    transfer: Transfer::None,
+                });
+            }
+        }
+        barriers
+    }
+
+    fn create_render_pass(
+        device: &B::Device,
+        graph: &FrameGraph<'g, B, D>,
+        pass: PassRef,
+        resources: &PhysicalResources<B>,
+        render_passes: &mut RenderPassCache<'g, B>,
+        images: &[(ImageRef, usize, image::Layout, Option<usize>)],
+    ) -> Result<CompiledRenderPass<B>, CompileError> {
+        let mut descriptions = Vec::new();
+        let mut attachments = Vec::new();
+        let mut clear_values = Vec::new();
+        let (mut colors, mut depth_stencil, mut inputs) = (Vec::new(), None, Vec::new());
+
+        let reads = graph.dependencies(pass).filter_map(|(producer, dependency)| match *dependency {
+            PassDependency::ReadImage(i, ImageRead::Attachment(info), _) => Some((ImageRef(producer, i), info, None)),
+            _ => None,
+        });
+        let writes = graph.get_pass(pass).images.iter().enumerate().filter_map(|(i, image)| match image.write_type {
+            ImageWrite::Attachment(info, clear) => Some((ImageRef(pass, i), info, Some(clear))),
+            _ => None,
+        });
+
+        for (image, info, write) in reads.chain(writes).collect::<Vec<_>>() {
+            let &(_, index, layout, view) = images.iter().find(|&&(i, _, _, _)| i == image).unwrap();
+            let physical = resources.physical_image(index);
+            let view = view.ok_or_else(|| CompileError::Resource {
+                resource: graph.describe_image(image),
+                error: Box::new(CompileError::NoAttachmentView),
+            })?;
+            let format = resources.view(view).key.format;
+            let load = match write {
+                Some(true) => pass::AttachmentLoadOp::Clear,
+                Some(false) if !is_version(graph, image) => pass::AttachmentLoadOp::DontCare,
+                _ => pass::AttachmentLoadOp::Load,
+            };
+            let depth = is_depth(&physical.info);
+            let ops = pass::AttachmentOps::new(load, pass::AttachmentStoreOp::Store);
+            descriptions.push(pass::Attachment {
+                format: Some(format),
+                samples: info.samples,
+                ops: ops,
+                stencil_ops: if depth { ops } else { pass::AttachmentOps::DONT_CARE },
+                layouts: layout..layout,
+            });
+
+            let reference = (attachments.len(), layout);
+            match write {
+                None => inputs.push(reference),
+                Some(_) if depth => depth_stencil = Some(reference),
+                Some(_) => colors.push(reference),
+            }
+            clear_values.push(if depth {
+                ClearValue::DepthStencil(ClearDepthStencil(1.0, 0)).into()
+            } else {
+                ClearValue::Color(ClearColor::Float([0.0; 4])).into()
+            });
+            attachments.push(RenderAttachment {
+                image: index,
+                view: view,
+            });
+        }
+
+        let extent = attachments.first()
+            .map(|a| resources.physical_image(a.image).info.kind.level_extent(resources.view(a.view).key.range.levels.start))
+            .unwrap_or(image::Extent { width: 1, height: 1, depth: 1 });
+
+        let key = RenderPassKey {
+            attachments: descriptions,
+            colors: colors,
+            depth_stencil: depth_stencil,
+            inputs: inputs,
+        };
+        let handle = render_passes.get(key, |key| {
+            let subpass = pass::SubpassDesc {
+                colors: &key.colors,
+                depth_stencil: key.depth_stencil.as_ref(),
+                inputs: &key.inputs,
+                resolves: &[],
+                preserves: &[],
+            };
+            let dependencies: &[pass::SubpassDependency] = &[];
+            let mut handle = device.create_render_pass(&key.attachments, &[subpass], dependencies)?;
+            if let Some(name) = graph.pass_name(pass) {
+                device.set_render_pass_name(&mut handle, name);
+            }
+            Ok(handle)
+        })?;
+
+        Ok(CompiledRenderPass {
+            handle: handle,
+            attachments: attachments,
+            extent: extent,
+            render_area: Rect { x: 0, y: 0, w: extent.width as _, h: extent.height as _ },
+            clear_values: clear_values,
+        })
+    }
+}
+
+// The view `pass` takes of an image it accesses, creating it if no other access shares it. Transfer passes
+// and images that can't be bound through a view get none.
+fn pass_view<B: Backend, D>(
+    device: &B::Device,
+    graph: &FrameGraph<B, D>,
+    resources: &mut PhysicalResources<B>,
+    pass: &RenderPass<B, D>,
+    image: ImageRef,
+    index: usize,
+    attachment_format: Option<format::Format>,
+    range: image::SubresourceRange,
+) -> Result<Option<usize>, CompileError> {
+    let info = resources.physical_image(index).info;
+    let viewable = image::Usage::SAMPLED | image::Usage::STORAGE | image::Usage::COLOR_ATTACHMENT |
+        image::Usage::DEPTH_STENCIL_ATTACHMENT | image::Usage::INPUT_ATTACHMENT;
+    match pass.kind {
+        RenderPassKind::Transfer(_) => return Ok(None),
+        _ if !info.usage.intersects(viewable) => return Ok(None),
+        _ => (),
+    }
+    let key = view_key(pass, image, &info, attachment_format, range);
+    resources.view_index(device, index, key).map(Some).map_err(|error| CompileError::Resource {
+        resource: graph.describe_image(image),
+        error: Box::new(error),
+    })
+}
+
+// The view a pass takes of an image it accesses: in the format and swizzle the pass asked for, if any, over
+// the range it accesses. Attachments are always viewed in their own format, and one mip level at a time.
+fn view_key<B: Backend, D>(
+    pass: &RenderPass<B, D>,
+    image: ImageRef,
+    info: &ImageCreateInfo,
+    attachment_format: Option<format::Format>,
+    mut range: image::SubresourceRange,
+) -> ViewKey {
+    let requested = pass.views.iter().find(|&&(i, _, _)| i == image).map(|&(_, format, swizzle)| (format, swizzle));
+    let (view_format, swizzle) = requested.unwrap_or((info.format, format::Swizzle::NO));
+    if attachment_format.is_some() {
+        range.levels = range.levels.start..range.levels.start + 1;
+    }
+    ViewKey {
+        format: attachment_format.unwrap_or(view_format),
+        swizzle: swizzle,
+        range: range,
+    }
+}
+
+// Whether `image` was created by writing to an earlier version, rather than from scratch.
+fn is_version<B: Backend, D>(graph: &FrameGraph<B, D>, image: ImageRef) -> bool {
+    graph.dependencies(image.0).any(|(_, dependency)| match *dependency {
+        PassDependency::CopyImage(_, new) => new == image.1,
+        _ => false,
+    })
+}
+
+fn is_buffer_version<B: Backend, D>(graph: &FrameGraph<B, D>, buffer: BufferRef) -> bool {
+    graph.dependencies(buffer.0).any(|(_, dependency)| match *dependency {
+        PassDependency::CopyBuffer(_, new) => new == buffer.1,
+        _ => false,
+    })
+}
+
+impl<'g, B: Backend, D> Drop for CompiledFrameGraph<'g, B, D> {
+    fn drop(&mut self) {
+        for pass in self.passes.drain(..) {
+            if let Some(render_pass) = pass.render_pass {
+                release_render_pass(self.device, render_pass.handle);
+            }
+        }
+        self.resources.destroy(self.device);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use gfx_hal::{format, image, Backend, Device, MemoryType, MemoryTypeId};
+use gfx_hal::memory::Properties;
+
+use crate::graph::{
+    FrameGraph, PassRef, QueueType, BufferCreateInfo, BufferRef, ImageCreateInfo, ImageRef, ImageRead, ImageWrite,
+    internal::{FrameGraphInternals, PassDependency, RenderPass, RenderPassKind},
+};
+use crate::compiled::{CompileError, Imports};
+
+#[derive(Copy, Clone, PartialEq)]
+enum Origin {
+    Transient,
+    Imported,
+    History,
+}
+
+struct Chain {
+    root: (PassRef, usize),
+    origin: Origin,
+    // The queue every pass using the chain runs on, or None if it is used from more than one.
+    queue: Option<QueueType>,
+    first: usize,
+    last: usize,
+}
+
+// Transient resources have one copy for every frame in flight. The current version of a history resource
+// has one more, so the copy written last frame is still around to be read as the previous version.
+enum Backing<T, B: Backend> {
+    Transient(Vec<(T, B::Memory)>),
+    Imported,
+    // Shares the copies of the current version with the given physical index, one frame behind.
+    Previous(usize),
+}
+
+pub(crate) struct Image<B: Backend> {
+    pub info: ImageCreateInfo,
+    pub root: ImageRef,
+    backing: Backing<B::Image, B>,
+}
+
+pub(crate) struct Buffer<B: Backend> {
+    pub info: BufferCreateInfo,
+    pub root: BufferRef,
+    backing: Backing<B::Buffer, B>,
+}
+
+// What sets apart the views a pass can take of an image.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub(crate) struct ViewKey {
+    pub format: format::Format,
+    pub swizzle: format::Swizzle,
+    pub range: image::SubresourceRange,
+}
+
+// A view of a physical image, with a handle for each of its copies. Views of imported images have none,
+// since the image can change from frame to frame, and are created by the renderer instead.
+pub(crate) struct View<B: Backend> {
+    pub image: usize,
+    pub key: ViewKey,
+    handles: Vec<B::ImageView>,
+}
+
+pub(crate) struct PhysicalResources<B: Backend> {
+    image_table: HashMap<ImageRef, usize>,
+    buffer_table: HashMap<BufferRef, usize>,
+    physical_images: Vec<Image<B>>,
+    physical_buffers: Vec<Buffer<B>>,
+    views: Vec<View<B>>,
+    view_table: HashMap<(usize, ViewKey), usize>,
+    // How many version chains share each physical resource.
+    image_chains: Vec<usize>,
+    buffer_chains: Vec<usize>,
+}
+
+impl<B: Backend> PhysicalResources<B> {
+    pub(crate) fn new<D>(
+        device: &B::Device,
+        memory_types: &[MemoryType],
+        graph: &FrameGraph<B, D>,
+        schedule: &[PassRef],
+        frames: usize,
+    ) -> Result<Self, CompileError> {
+        let (image_roots, image_chains) = version_chains(graph, schedule, |pass| pass.images.len(), |dep| match *dep {
+            PassDependency::ReadImage(i, ..) => Some((i, None)),
+            PassDependency::CopyImage(i, new) => Some((i, Some(new))),
+            _ => None,
+        });
+        let (buffer_roots, buffer_chains) = version_chains(graph, schedule, |pass| pass.buffers.len(), |dep| match *dep {
+            PassDependency::ReadBuffer(b, _) => Some((b, None)),
+            PassDependency::CopyBuffer(b, new) => Some((b, Some(new))),
+            _ => None,
+        });
+
+        let image_info = |(pass, index): (PassRef, usize)| graph.get_image(ImageRef(pass, index)).info;
+        let (image_slots, image_roots_by_slot) = alias(&image_chains, &image_info);
+        let buffer_info = |(pass, index): (PassRef, usize)| graph.get_buffer(BufferRef(pass, index)).info;
+        let (buffer_slots, buffer_roots_by_slot) = alias(&buffer_chains, &buffer_info);
+
+        // Images viewed in a format other than their own have to be created allowing it.
+        let mut mutable_format = vec![false; image_roots_by_slot.len()];
+        for &pass in schedule {
+            let render_pass = graph.get_pass(pass);
+            let requested = render_pass.views.iter().map(|&(image, format, _)| (image, Some(format)));
+            let attachments = graph.dependencies(pass).filter_map(|(producer, dependency)| match *dependency {
+                PassDependency::ReadImage(i, ImageRead::Attachment(info), _) => Some((ImageRef(producer, i), info.format)),
+                _ => None,
+            });
+            let written = render_pass.images.iter().enumerate().filter_map(|(i, image)| match image.write_type {
+                ImageWrite::Attachment(info, _) => Some((ImageRef(pass, i), info.format)),
+                _ => None,
+            });
+            for (image, format) in requested.chain(attachments).chain(written) {
+                let chain = image_roots.get(&(image.0, image.1));
+                if let (Some(&chain), Some(format)) = (chain, format) {
+                    if format != image_info((image.0, image.1)).format {
+                        mutable_format[image_slots[chain]] = true;
+                    }
+                }
+            }
+        }
+
+        let mut image_chains = vec![0; image_roots_by_slot.len()];
+        for &slot in &image_slots {
+            image_chains[slot] += 1;
+        }
+        let mut buffer_chains = vec![0; buffer_roots_by_slot.len()];
+        for &slot in &buffer_slots {
+            buffer_chains[slot] += 1;
+        }
+
+        let mut resources = PhysicalResources {
+            image_table: image_roots.into_iter().map(|((p, i), c)| (ImageRef(p, i), image_slots[c])).collect(),
+            buffer_table: buffer_roots.into_iter().map(|((p, b), c)| (BufferRef(p, b), buffer_slots[c])).collect(),
+            physical_images: Vec::with_capacity(image_roots_by_slot.len()),
+            physical_buffers: Vec::with_capacity(buffer_roots_by_slot.len()),
+            views: Vec::new(),
+            view_table: HashMap::new(),
+            image_chains: image_chains,
+            buffer_chains: buffer_chains,
+        };
+        let created = resources.create_backings(
+            device, memory_types, graph, image_roots_by_slot, buffer_roots_by_slot, &mutable_format, frames
+        );
+        if let Err(error) = created {
+            resources.destroy(device);
+            return Err(error)
+        }
+        Ok(resources)
+    }
+
+    // Creates every copy of the transient resources, adding each to `self` as soon as it exists, so that
+    // whatever was created before a failure is left for `destroy`.
+    fn create_backings<D>(
+        &mut self,
+        device: &B::Device,
+        memory_types: &[MemoryType],
+        graph: &FrameGraph<B, D>,
+        image_roots: Vec<((PassRef, usize), Origin)>,
+        buffer_roots: Vec<((PassRef, usize), Origin)>,
+        mutable_format: &[bool],
+        frames: usize,
+    ) -> Result<(), CompileError> {
+        for (slot, (root, origin)) in image_roots.into_iter().enumerate() {
+            let image = ImageRef(root.0, root.1);
+            let info = graph.get_image(image).info;
+            self.physical_images.push(Image {
+                info: info,
+                root: image,
+                backing: match origin {
+                    Origin::Imported => Backing::Imported,
+                    Origin::History if root.1 == 0 => Backing::Previous(self.image_table[&ImageRef(root.0, 1)]),
+                    _ => Backing::Transient(Vec::new()),
+                },
+            });
+
+            let capabilities = if mutable_format[slot] {
+                image::ViewCapabilities::MUTABLE_FORMAT
+            } else {
+                image::ViewCapabilities::empty()
+            };
+            let copies = if origin == Origin::History { frames + 1 } else { frames };
+            let name = graph.get_image(image).name.as_ref().map(String::as_str);
+            if let Backing::Transient(ref mut created) = self.physical_images[slot].backing {
+                for _ in 0..copies {
+                    let copy = create_image(device, memory_types, &info, capabilities, name).map_err(|error| {
+                        CompileError::Resource { resource: graph.describe_image(image), error: Box::new(error) }
+                    })?;
+                    created.push(copy);
+                }
+            }
+        }
+
+        for (slot, (root, origin)) in buffer_roots.into_iter().enumerate() {
+            let buffer = BufferRef(root.0, root.1);
+            let info = graph.get_buffer(buffer).info;
+            self.physical_buffers.push(Buffer {
+                info: info,
+                root: buffer,
+                backing: match origin {
+                    Origin::Imported => Backing::Imported,
+                    Origin::History if root.1 == 0 => Backing::Previous(self.buffer_table[&BufferRef(root.0, 1)]),
+                    _ => Backing::Transient(Vec::new()),
+                },
+            });
+
+            let copies = if origin == Origin::History { frames + 1 } else { frames };
+            let name = graph.get_buffer(buffer).name.as_ref().map(String::as_str);
+            if let Backing::Transient(ref mut created) = self.physical_buffers[slot].backing {
+                for _ in 0..copies {
+                    let copy = create_buffer(device, memory_types, &info, name).map_err(|error| {
+                        CompileError::Resource { resource: graph.describe_buffer(buffer), error: Box::new(error) }
+                    })?;
+                    created.push(copy);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn image_index(&self, image: ImageRef) -> usize {
+        *self.image_table.get(&image).expect("Invalid image handle!")
+    }
+
+    pub(crate) fn buffer_index(&self, buffer: BufferRef) -> usize {
+        *self.buffer_table.get(&buffer).expect("Invalid buffer handle!")
+    }
+
+    pub(crate) fn find_image(&self, image: ImageRef) -> Option<usize> {
+        self.image_table.get(&image).cloned()
+    }
+
+    pub(crate) fn find_buffer(&self, buffer: BufferRef) -> Option<usize> {
+        self.buffer_table.get(&buffer).cloned()
+    }
+
+    pub(crate) fn is_aliased_image(&self, index: usize) -> bool {
+        self.image_chains[index] > 1
+    }
+
+    pub(crate) fn is_aliased_buffer(&self, index: usize) -> bool {
+        self.buffer_chains[index] > 1
+    }
+
+    pub(crate) fn physical_image(&self, index: usize) -> &Image<B> {
+        &self.physical_images[index]
+    }
+
+    pub(crate) fn physical_buffer(&self, index: usize) -> &Buffer<B> {
+        &self.physical_buffers[index]
+    }
+
+    // Accesses with the same key share a view.
+    pub(crate) fn view_index(&mut self, device: &B::Device, index: usize, key: ViewKey) -> Result<usize, CompileError> {
+        if let Some(&view) = self.view_table.get(&(index, key.clone())) {
+            return Ok(view)
+        }
+        self.view_table.insert((index, key.clone()), self.views.len());
+        self.views.push(View { image: index, key: key, handles: Vec::new() });
+        let view = self.views.len() - 1;
+
+        let image = &self.physical_images[index];
+        let copies = match image.backing {
+            Backing::Transient(ref copies) => copies,
+            Backing::Imported => return Ok(view),
+            Backing::Previous(current) => match self.physical_images[current].backing {
+                Backing::Transient(ref copies) => copies,
+                _ => unreachable!(),
+            },
+        };
+        // Handles are added as they are created, so a failure still leaves them to be destroyed.
+        for &(ref copy, _) in copies {
+            let key = &self.views[view].key;
+            let handle = device.create_image_view(
+                copy,
+                view_kind(image.info.kind, &key.range.layers),
+                key.format,
+                key.swizzle,
+                key.range.clone(),
+            )?;
+            self.views[view].handles.push(handle);
+        }
+        Ok(view)
+    }
+
+    pub(crate) fn view(&self, view: usize) -> &View<B> {
+        &self.views[view]
+    }
+
+    pub(crate) fn view_count(&self) -> usize {
+        self.views.len()
+    }
+
+    pub(crate) fn is_imported_view(&self, view: usize) -> bool {
+        match self.physical_images[self.views[view].image].backing {
+            Backing::Imported => true,
+            _ => false,
+        }
+    }
+
+    // Which copy of its image a view looks at in `frame`, or None for views of imported images.
+    pub(crate) fn view_copy(&self, view: usize, frame: usize) -> Option<usize> {
+        let copies = self.views[view].handles.len();
+        match self.physical_images[self.views[view].image].backing {
+            Backing::Transient(_) => Some(frame % copies),
+            Backing::Imported => None,
+            Backing::Previous(_) => Some((frame + copies - 1) % copies),
+        }
+    }
+
+    pub(crate) fn get_view(&self, view: usize, frame: usize) -> Option<&B::ImageView> {
+        self.view_copy(view, frame).map(|copy| &self.views[view].handles[copy])
+    }
+
+    pub(crate) fn image_count(&self) -> usize {
+        self.physical_images.len()
+    }
+
+    pub(crate) fn buffer_count(&self) -> usize {
+        self.physical_buffers.len()
+    }
+
+    // `frame` counts every frame rendered, rather than just those in flight, so that history resources
+    // can tell which copy was written last.
+    pub(crate) fn get_image<'a>(&'a self, index: usize, frame: usize, imports: &Imports<'a, B>) -> &'a B::Image {
+        let image = &self.physical_images[index];
+        match image.backing {
+            Backing::Transient(ref copies) => &copies[frame % copies.len()].0,
+            Backing::Imported => imports.get_image(image.root),
+            Backing::Previous(current) => match self.physical_images[current].backing {
+                Backing::Transient(ref copies) => &copies[(frame + copies.len() - 1) % copies.len()].0,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    pub(crate) fn get_buffer<'a>(&'a self, index: usize, frame: usize, imports: &Imports<'a, B>) -> &'a B::Buffer {
+        let buffer = &self.physical_buffers[index];
+        match buffer.backing {
+            Backing::Transient(ref copies) => &copies[frame % copies.len()].0,
+            Backing::Imported => imports.get_buffer(buffer.root),
+            Backing::Previous(current) => match self.physical_buffers[current].backing {
+                Backing::Transient(ref copies) => &copies[(frame + copies.len() - 1) % copies.len()].0,
+                _ => unreachable!(),
+            },
+        }
+    }
+
+    pub(crate) fn destroy(&mut self, device: &B::Device) {
+        self.view_table.clear();
+        for view in self.views.drain(..) {
+            for handle in view.handles {
+                device.destroy_image_view(handle);
+            }
+        }
+        for image in self.physical_images.drain(..) {
+            if let Backing::Transient(copies) = image.backing {
+                for (handle, memory) in copies {
+                    device.destroy_image(handle);
+                    device.free_memory(memory);
+                }
+            }
+        }
+        for buffer in self.physical_buffers.drain(..) {
+            if let Backing::Transient(copies) = buffer.backing {
+                for (handle, memory) in copies {
+                    device.destroy_buffer(handle);
+                    device.free_memory(memory);
+                }
+            }
+        }
+    }
+}
+
+// Groups every version of a resource with the version it was written from, recording the span of the
+// schedule over which each group is in use. `uses` picks out the dependencies on this kind of resource,
+// giving the index used and, for writes, the index of the new version.
+fn version_chains<'p, B, D, C, U>(
+    graph: &FrameGraph<'p, B, D>,
+    schedule: &[PassRef],
+    count: C,
+    uses: U,
+) -> (HashMap<(PassRef, usize), usize>, Vec<Chain>)
+    where B: Backend,
+          C: Fn(&RenderPass<'p, B, D>) -> usize,
+          U: Fn(&PassDependency) -> Option<(usize, Option<usize>)>,
+{
+    let mut roots = HashMap::new();
+    let mut chains = Vec::new();
+    for (position, &pass) in schedule.iter().enumerate() {
+        let render_pass = graph.get_pass(pass);
+        for (producer, dependency) in graph.dependencies(pass) {
+            if let Some((used, new)) = uses(dependency) {
+                let chain = roots[&(producer, used)];
+                chains[chain].last = position;
+                if chains[chain].queue != Some(render_pass.queue) {
+                    chains[chain].queue = None;
+                }
+                if let Some(new) = new {
+                    roots.insert((pass, new), chain);
+                }
+            }
+        }
+
+        let origin = match render_pass.kind {
+            RenderPassKind::Import => Origin::Imported,
+            RenderPassKind::History => Origin::History,
+            _ => Origin::Transient,
+        };
+        for index in 0..count(render_pass) {
+            if !roots.contains_key(&(pass, index)) {
+                roots.insert((pass, index), chains.len());
+                chains.push(Chain {
+                    root: (pass, index),
+                    origin: origin,
+                    queue: Some(render_pass.queue),
+                    first: position,
+                    last: position,
+                });
+            }
+        }
+    }
+    (roots, chains)
+}
+
+// Lets transient chains with identical descriptions share a physical resource when their lifetimes do not
+// overlap. Chains are created in schedule order, so they are already sorted by their first use. Queues run
+// side by side whatever the schedule says, so only chains kept to one and the same queue share.
+fn alias<T: PartialEq, F>(chains: &[Chain], info: F) -> (Vec<usize>, Vec<((PassRef, usize), Origin)>)
+    where F: Fn((PassRef, usize)) -> T
+{
+    let mut slots: Vec<(T, usize, Option<QueueType>, ((PassRef, usize), Origin))> = Vec::new();
+    let assignment = chains.iter().map(|chain| {
+        let description = info(chain.root);
+        let free = slots.iter().position(|&(ref d, last, queue, (_, origin))| {
+            chain.origin == Origin::Transient && origin == Origin::Transient && *d == description &&
+                last < chain.first && chain.queue.is_some() && queue == chain.queue
+        });
+        match free {
+            Some(slot) => {
+                slots[slot].1 = chain.last;
+                slot
+            },
+            None => {
+                slots.push((description, chain.last, chain.queue, (chain.root, chain.origin)));
+                slots.len() - 1
+            },
+        }
+    }).collect();
+    (assignment, slots.into_iter().map(|(_, _, _, root)| root).collect())
+}
+
+pub(crate) fn view_kind(kind: image::Kind, layers: &Range<image::Layer>) -> image::ViewKind {
+    let array = layers.end - layers.start > 1;
+    match kind {
+        image::Kind::D1(..) if array => image::ViewKind::D1Array,
+        image::Kind::D1(..) => image::ViewKind::D1,
+        image::Kind::D2(..) if array => image::ViewKind::D2Array,
+        image::Kind::D2(..) => image::ViewKind::D2,
+        image::Kind::D3(..) => image::ViewKind::D3,
+    }
+}
+
+fn device_local_memory(memory_types: &[MemoryType], type_mask: u64) -> Option<MemoryTypeId> {
+    memory_types.iter().enumerate()
+        .position(|(id, ty)| type_mask & (1 << id) != 0 && ty.properties.contains(Properties::DEVICE_LOCAL))
+        .map(MemoryTypeId)
+}
+
+fn create_image<B: Backend>(
+    device: &B::Device,
+    memory_types: &[MemoryType],
+    info: &ImageCreateInfo,
+    capabilities: image::ViewCapabilities,
+    name: Option<&str>,
+) -> Result<(B::Image, B::Memory), CompileError> {
+    let mut image =
+        device.create_image(info.kind, info.mip_levels, info.format, info.tiling, info.usage, capabilities)?;
+    let requirements = device.get_image_requirements(&image);
+    let memory = match allocate(device, memory_types, requirements.type_mask, requirements.size) {
+        Ok(memory) => memory,
+        Err(error) => {
+            device.destroy_image(image);
+            return Err(error)
+        },
+    };
+    if let Err(error) = device.bind_image_memory(&memory, 0, &mut image) {
+        device.destroy_image(image);
+        device.free_memory(memory);
+        return Err(error.into())
+    }
+    if let Some(name) = name {
+        device.set_image_name(&mut image, name);
+    }
+    Ok((image, memory))
+}
+
+fn create_buffer<B: Backend>(
+    device: &B::Device,
+    memory_types: &[MemoryType],
+    info: &BufferCreateInfo,
+    name: Option<&str>,
+) -> Result<(B::Buffer, B::Memory), CompileError> {
+    let mut buffer = device.create_buffer(info.size, info.usage)?;
+    let requirements = device.get_buffer_requirements(&buffer);
+    let memory = match allocate(device, memory_types, requirements.type_mask, requirements.size) {
+        Ok(memory) => memory,
+        Err(error) => {
+            device.destroy_buffer(buffer);
+            return Err(error)
+        },
+    };
+    if let Err(error) = device.bind_buffer_memory(&memory, 0, &mut buffer) {
+        device.destroy_buffer(buffer);
+        device.free_memory(memory);
+        return Err(error.into())
+    }
+    if let Some(name) = name {
+        device.set_buffer_name(&mut buffer, name);
+    }
+    Ok((buffer, memory))
+}
+
+fn allocate<B: Backend>(
+    device: &B::Device,
+    memory_types: &[MemoryType],
+    type_mask: u64,
+    size: u64,
+) -> Result<B::Memory, CompileError> {
+    let memory_type = device_local_memory(memory_types, type_mask).ok_or(CompileError::NoMemoryType)?;
+    Ok(device.allocate_memory(memory_type, size)?)
+}
\ No newline at end of file
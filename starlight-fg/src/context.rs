@@ -1,44 +1,146 @@
 use crate::{BufferRef, ImageRef};
+use crate::serial::{RendererError, SamplerCache};
 
 use std::collections::HashMap;
 use std::borrow::Borrow;
+use std::ops::Range;
+use std::sync::Arc;
 
-use gfx_hal::{buffer, image, Backend};
+use gfx_hal::{buffer, image, Backend, DrawCount, IndexCount, InstanceCount, VertexCount, VertexOffset};
 use gfx_hal::command::{
-    CommandBuffer, MultiShot, Primary, ClearColor, ClearDepthStencil, DescriptorSetOffset
+    RawCommandBuffer, ClearColor, ClearDepthStencil, DescriptorSetOffset,
+    BufferCopy, ImageCopy, BufferImageCopy, ImageBlit,
 };
-use gfx_hal::queue::{Graphics, Compute};
+use gfx_hal::pso::WorkGroupCount;
 
-struct ImageState<B: Backend> {
-    handle: B::Image,
+struct ImageState<'r, B: Backend> {
+    handle: &'r B::Image,
     layout: image::Layout,
+    view: Option<&'r B::ImageView>,
 }
 
-struct BufferState<B: Backend> {
-    handle: B::Buffer,
+struct BufferState<'r, B: Backend> {
+    handle: &'r B::Buffer,
 }
 
-struct ResourceHandles<B: Backend> {
-    images: HashMap<ImageRef, ImageState<B>>,
-    buffers: HashMap<BufferRef, BufferState<B>>,
+pub(crate) struct ResourceHandles<'r, B: Backend> {
+    images: HashMap<ImageRef, ImageState<'r, B>>,
+    buffers: HashMap<BufferRef, BufferState<'r, B>>,
+    frame: usize,
+    history_valid: bool,
+    samplers: &'r SamplerCache<'r, B>,
 }
 
-impl<B: Backend> ResourceHandles<B> {
-    fn get_image_state(&self, image: ImageRef) -> &ImageState<B> {
+impl<'r, B: Backend> ResourceHandles<'r, B> {
+    pub(crate) fn new(frame: usize, history_valid: bool, samplers: &'r SamplerCache<'r, B>) -> Self {
+        ResourceHandles {
+            images: HashMap::new(),
+            buffers: HashMap::new(),
+            frame: frame,
+            history_valid: history_valid,
+            samplers: samplers,
+        }
+    }
+
+    pub(crate) fn insert_image(
+        &mut self,
+        image: ImageRef,
+        handle: &'r B::Image,
+        layout: image::Layout,
+        view: Option<&'r B::ImageView>,
+    ) {
+        self.images.insert(image, ImageState { handle: handle, layout: layout, view: view });
+    }
+
+    pub(crate) fn insert_buffer(&mut self, buffer: BufferRef, handle: &'r B::Buffer) {
+        self.buffers.insert(buffer, BufferState { handle: handle });
+    }
+
+    fn get_image_state(&self, image: ImageRef) -> &ImageState<'r, B> {
         self.images.get(&image).expect("Invalid image handle!")
     }
 
-    fn get_buffer_state(&self, buffer: BufferRef) -> &BufferState<B> {
+    fn get_buffer_state(&self, buffer: BufferRef) -> &BufferState<'r, B> {
         self.buffers.get(&buffer).expect("Invalid buffer handle!")
     }
 }
 
+// What a pass recorded through its context, for the renderer's statistics.
+#[derive(Copy, Clone, Default, Debug)]
+pub(crate) struct Counters {
+    pub draws: usize,
+    pub dispatches: usize,
+    pub descriptor_binds: usize,
+}
+
 pub struct GraphicsContext<'c, B: Backend> {
-    buffer: CommandBuffer<'c, B, Graphics, MultiShot, Primary>,
-    resources: ResourceHandles<B>,
+    buffer: &'c mut B::CommandBuffer,
+    resources: &'c ResourceHandles<'c, B>,
+    secondary: Vec<GraphicsContext<'c, B>>,
+    counters: Counters,
 }
 
 impl<'c, B: Backend> GraphicsContext<'c, B> {
+    pub(crate) fn new(buffer: &'c mut B::CommandBuffer, resources: &'c ResourceHandles<'c, B>) -> Self {
+        Self::with_secondary(buffer, resources, None)
+    }
+
+    pub(crate) fn with_secondary<I>(buffer: &'c mut B::CommandBuffer, resources: &'c ResourceHandles<'c, B>, secondary: I) -> Self
+        where I: IntoIterator<Item = &'c mut B::CommandBuffer>
+    {
+        GraphicsContext {
+            buffer: buffer,
+            resources: resources,
+            secondary: secondary.into_iter().map(|buffer| GraphicsContext::new(buffer, resources)).collect(),
+            counters: Counters::default(),
+        }
+    }
+
+    // The secondary command buffers of a pass that records into them, each with a context that can be
+    // handed to another thread. They are executed in order once the pass returns. Such a pass must record
+    // everything through them, never through this context itself.
+    pub fn secondary_buffers(&mut self) -> &mut [GraphicsContext<'c, B>] {
+        &mut self.secondary
+    }
+
+    // The frame in flight being recorded, for picking out per-frame copies of descriptor sets and other
+    // resources owned by the pass.
+    pub fn frame(&self) -> usize {
+        self.resources.frame
+    }
+
+    // False on the first frame, when the previous versions of history resources hold nothing yet.
+    pub fn history_valid(&self) -> bool {
+        self.resources.history_valid
+    }
+
+    // The view of `image` made for this pass, over the subresources it declared and in the format it asked
+    // for, to write into descriptor sets.
+    pub fn image_view(&self, image: ImageRef) -> &'c B::ImageView {
+        let resources: &'c ResourceHandles<'c, B> = self.resources;
+        resources.get_image_state(image).view.expect("Image has no view, as its usage allows none!")
+    }
+
+    // A sampler shared with every other pass asking for the same one, to write into descriptor sets along
+    // with image views. It lives as long as the renderer.
+    pub fn sampler(&self, info: &image::SamplerInfo) -> Result<Arc<B::Sampler>, RendererError> {
+        self.resources.samplers.get(info)
+    }
+
+    // Opens a named region in captures of the frame, nested inside the pass's own region. `color` is
+    // packed as 0xRRGGBBAA.
+    pub fn begin_marker(&mut self, name: &str, color: u32) {
+        self.buffer.begin_debug_marker(name, color)
+    }
+
+    pub fn end_marker(&mut self) {
+        self.buffer.end_debug_marker()
+    }
+
+    pub fn insert_marker(&mut self, name: &str, color: u32) {
+        self.buffer.insert_debug_marker(name, color)
+    }
+
     pub fn clear_image<T>(
         &mut self,
         image: ImageRef,
@@ -50,7 +152,7 @@ impl<'c, B: Backend> GraphicsContext<'c, B> {
         T::Item: Borrow<image::SubresourceRange>,
     {
         let image = self.resources.get_image_state(image);
-        self.buffer.clear_image(&image.handle, image.layout, color, depth_stencil, subresource_ranges)
+        self.buffer.clear_image(image.handle, image.layout, color.into(), depth_stencil.into(), subresource_ranges)
     }
 
     /*
@@ -64,7 +166,7 @@ impl<'c, B: Backend> GraphicsContext<'c, B> {
         I: IntoIterator<Item = (BufferRef, buffer::Offset)>,
     {
         self.buffer.bind_vertex_buffers(first_binding, buffers.into_iter().map(
-            |(b, o)| (&self.resources.get_buffer_state(b).handle, o)
+            |(b, o)| (self.resources.get_buffer_state(b).handle, o)
         ));
     }
 
@@ -84,6 +186,226 @@ impl<'c, B: Backend> GraphicsContext<'c, B> {
         J: IntoIterator,
         J::Item: Borrow<DescriptorSetOffset>,
     {
+        self.counters.descriptor_binds += 1;
         self.buffer.bind_graphics_descriptor_sets(layout, first_set, sets, offsets)
     }
+
+    pub fn draw(&mut self, vertices: Range<VertexCount>, instances: Range<InstanceCount>) {
+        self.counters.draws += 1;
+        self.buffer.draw(vertices, instances)
+    }
+
+    pub fn draw_indexed(&mut self, indices: Range<IndexCount>, base_vertex: VertexOffset, instances: Range<InstanceCount>) {
+        self.counters.draws += 1;
+        self.buffer.draw_indexed(indices, base_vertex, instances)
+    }
+
+    pub fn draw_indirect(&mut self, buffer: BufferRef, offset: buffer::Offset, draw_count: DrawCount, stride: u32) {
+        self.counters.draws += 1;
+        self.buffer.draw_indirect(self.resources.get_buffer_state(buffer).handle, offset, draw_count, stride)
+    }
+
+    pub fn draw_indexed_indirect(&mut self, buffer: BufferRef, offset: buffer::Offset, draw_count: DrawCount, stride: u32) {
+        self.counters.draws += 1;
+        self.buffer.draw_indexed_indirect(self.resources.get_buffer_state(buffer).handle, offset, draw_count, stride)
+    }
+
+    pub(crate) fn counters(&self) -> Counters {
+        self.secondary.iter().map(GraphicsContext::counters).fold(self.counters, |total, counters| Counters {
+            draws: total.draws + counters.draws,
+            dispatches: total.dispatches + counters.dispatches,
+            descriptor_binds: total.descriptor_binds + counters.descriptor_binds,
+        })
+    }
+}
+
+pub struct ComputeContext<'c, B: Backend> {
+    buffer: &'c mut B::CommandBuffer,
+    resources: &'c ResourceHandles<'c, B>,
+    counters: Counters,
+}
+
+impl<'c, B: Backend> ComputeContext<'c, B> {
+    pub(crate) fn new(buffer: &'c mut B::CommandBuffer, resources: &'c ResourceHandles<'c, B>) -> Self {
+        ComputeContext {
+            buffer: buffer,
+            resources: resources,
+            counters: Counters::default(),
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.resources.frame
+    }
+
+    pub fn history_valid(&self) -> bool {
+        self.resources.history_valid
+    }
+
+    pub fn image_view(&self, image: ImageRef) -> &'c B::ImageView {
+        let resources: &'c ResourceHandles<'c, B> = self.resources;
+        resources.get_image_state(image).view.expect("Image has no view, as its usage allows none!")
+    }
+
+    pub fn sampler(&self, info: &image::SamplerInfo) -> Result<Arc<B::Sampler>, RendererError> {
+        self.resources.samplers.get(info)
+    }
+
+    pub fn begin_marker(&mut self, name: &str, color: u32) {
+        self.buffer.begin_debug_marker(name, color)
+    }
+
+    pub fn end_marker(&mut self) {
+        self.buffer.end_debug_marker()
+    }
+
+    pub fn insert_marker(&mut self, name: &str, color: u32) {
+        self.buffer.insert_debug_marker(name, color)
+    }
+
+    pub fn bind_pipeline(&mut self, pipeline: &B::ComputePipeline) {
+        self.buffer.bind_compute_pipeline(pipeline)
+    }
+
+    pub fn bind_descriptor_sets<I, J>(
+        &mut self,
+        layout: &B::PipelineLayout,
+        first_set: usize,
+        sets: I,
+        offsets: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<B::DescriptorSet>,
+        J: IntoIterator,
+        J::Item: Borrow<DescriptorSetOffset>,
+    {
+        self.counters.descriptor_binds += 1;
+        self.buffer.bind_compute_descriptor_sets(layout, first_set, sets, offsets)
+    }
+
+    pub fn dispatch(&mut self, count: WorkGroupCount) {
+        self.counters.dispatches += 1;
+        self.buffer.dispatch(count)
+    }
+
+    pub fn dispatch_indirect(&mut self, buffer: BufferRef, offset: buffer::Offset) {
+        self.counters.dispatches += 1;
+        self.buffer.dispatch_indirect(self.resources.get_buffer_state(buffer).handle, offset)
+    }
+
+    pub(crate) fn counters(&self) -> Counters {
+        self.counters
+    }
+}
+
+// Transfer passes may be placed on either a transfer or a graphics queue. Blits and clears are only
+// valid on the latter, which passes built with `blits` are guaranteed.
+pub struct TransferContext<'c, B: Backend> {
+    buffer: &'c mut B::CommandBuffer,
+    resources: &'c ResourceHandles<'c, B>,
+    graphics: bool,
+}
+
+impl<'c, B: Backend> TransferContext<'c, B> {
+    pub(crate) fn new(buffer: &'c mut B::CommandBuffer, resources: &'c ResourceHandles<'c, B>, graphics: bool) -> Self {
+        TransferContext {
+            buffer: buffer,
+            resources: resources,
+            graphics: graphics,
+        }
+    }
+
+    pub fn frame(&self) -> usize {
+        self.resources.frame
+    }
+
+    pub fn history_valid(&self) -> bool {
+        self.resources.history_valid
+    }
+
+    pub fn begin_marker(&mut self, name: &str, color: u32) {
+        self.buffer.begin_debug_marker(name, color)
+    }
+
+    pub fn end_marker(&mut self) {
+        self.buffer.end_debug_marker()
+    }
+
+    pub fn insert_marker(&mut self, name: &str, color: u32) {
+        self.buffer.insert_debug_marker(name, color)
+    }
+
+    pub fn copy_buffer<T>(&mut self, src: BufferRef, dst: BufferRef, regions: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<BufferCopy>,
+    {
+        let src = self.resources.get_buffer_state(src);
+        let dst = self.resources.get_buffer_state(dst);
+        self.buffer.copy_buffer(src.handle, dst.handle, regions)
+    }
+
+    pub fn copy_image<T>(&mut self, src: ImageRef, dst: ImageRef, regions: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<ImageCopy>,
+    {
+        let src = self.resources.get_image_state(src);
+        let dst = self.resources.get_image_state(dst);
+        self.buffer.copy_image(src.handle, src.layout, dst.handle, dst.layout, regions)
+    }
+
+    pub fn copy_buffer_to_image<T>(&mut self, src: BufferRef, dst: ImageRef, regions: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<BufferImageCopy>,
+    {
+        let src = self.resources.get_buffer_state(src);
+        let dst = self.resources.get_image_state(dst);
+        self.buffer.copy_buffer_to_image(src.handle, dst.handle, dst.layout, regions)
+    }
+
+    pub fn copy_image_to_buffer<T>(&mut self, src: ImageRef, dst: BufferRef, regions: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<BufferImageCopy>,
+    {
+        let src = self.resources.get_image_state(src);
+        let dst = self.resources.get_buffer_state(dst);
+        self.buffer.copy_image_to_buffer(src.handle, src.layout, dst.handle, regions)
+    }
+
+    pub fn blit_image<T>(&mut self, src: ImageRef, dst: ImageRef, filter: image::Filter, regions: T)
+    where
+        T: IntoIterator,
+        T::Item: Borrow<ImageBlit>,
+    {
+        assert!(self.graphics, "Blitting outside of the graphics queue, the pass needs to be built with `blits`");
+        let src = self.resources.get_image_state(src);
+        let dst = self.resources.get_image_state(dst);
+        self.buffer.blit_image(src.handle, src.layout, dst.handle, dst.layout, filter, regions)
+    }
+
+    pub fn fill_buffer(&mut self, buffer: BufferRef, range: Range<buffer::Offset>, data: u32) {
+        self.buffer.fill_buffer(self.resources.get_buffer_state(buffer).handle, range, data)
+    }
+
+    pub fn update_buffer(&mut self, buffer: BufferRef, offset: buffer::Offset, data: &[u8]) {
+        self.buffer.update_buffer(self.resources.get_buffer_state(buffer).handle, offset, data)
+    }
+
+    pub fn clear_image<T>(
+        &mut self,
+        image: ImageRef,
+        color: ClearColor,
+        depth_stencil: ClearDepthStencil,
+        subresource_ranges: T,
+    ) where
+        T: IntoIterator,
+        T::Item: Borrow<image::SubresourceRange>,
+    {
+        assert!(self.graphics, "Clearing outside of the graphics queue, the pass needs to be built with `blits`");
+        let image = self.resources.get_image_state(image);
+        self.buffer.clear_image(image.handle, image.layout, color.into(), depth_stencil.into(), subresource_ranges)
+    }
 }
\ No newline at end of file
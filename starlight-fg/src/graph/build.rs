@@ -1,66 +1,122 @@
-use gfx_hal::{buffer, format, image, pass, Backend};
+use std::ops::Range;
+
+use gfx_hal::{format, image, Backend};
 use crate::graph::{
     FrameGraph, PassRef,
-    BufferRef, BufferResource, 
+    BufferCreateInfo, BufferRef, BufferResource, 
     ImageCreateInfo, ImageRef, ImageResource, ImageRead, ImageWrite,
-    internal::{FrameGraphInternals, FrameGraphInternalsMut, PassDependency}
+    internal::{FrameGraphInternals, FrameGraphInternalsMut, PassDependency, pass_description}
 };
-use crate::pass::{GraphicsPass, ComputePass};
+use crate::pass::{GraphicsPass, ComputePass, TransferPass};
 
-pub trait BuildGraphicsPass<B: Backend> {
+pub trait BuildGraphicsPass<B: Backend, D = ()> {
     type Output;
-    type Pass: GraphicsPass<B>;
-    fn build(&self, builder: &mut GraphicsPassBuilder<B>) -> (Self::Output, Self::Pass);
+    type Pass: GraphicsPass<B, D>;
+    fn build(&self, builder: &mut GraphicsPassBuilder<B, D>) -> (Self::Output, Self::Pass);
 }
 
-impl<T, B: Backend, O, P: GraphicsPass<B>> BuildGraphicsPass<B> for T 
-    where T: Fn(&mut GraphicsPassBuilder<B>) -> (O, P) 
+impl<T, B: Backend, D, O, P: GraphicsPass<B, D>> BuildGraphicsPass<B, D> for T 
+    where T: Fn(&mut GraphicsPassBuilder<B, D>) -> (O, P) 
 {
     type Output = O;
     type Pass = P;
-    fn build(&self, builder: &mut GraphicsPassBuilder<B>) -> (O, P) {
+    fn build(&self, builder: &mut GraphicsPassBuilder<B, D>) -> (O, P) {
         self(builder)
     }
 }
 
-pub struct GraphicsPassBuilder<'g, 'p, B: Backend> {
-    graph: &'g mut FrameGraph<'p, B>,
+pub struct GraphicsPassBuilder<'g, 'p, B: Backend, D = ()> {
+    graph: &'g mut FrameGraph<'p, B, D>,
     pub(crate) buffers: Vec<BufferResource>,
     pub(crate) images: Vec<ImageResource>,
+    pub(crate) views: Vec<(ImageRef, format::Format, format::Swizzle)>,
+    pub(crate) side_effects: bool,
+    pub(crate) secondary_buffers: usize,
+    pub(crate) name: Option<String>,
     pass: PassRef,
 }
 
-impl<'g, 'p, B: Backend> GraphicsPassBuilder<'g, 'p, B> {
-    pub(crate) fn new(graph: &'g mut FrameGraph<'p, B>, pass: PassRef) -> Self {
+impl<'g, 'p, B: Backend, D> GraphicsPassBuilder<'g, 'p, B, D> {
+    pub(crate) fn new(graph: &'g mut FrameGraph<'p, B, D>, pass: PassRef, name: Option<&str>) -> Self {
         GraphicsPassBuilder { 
             graph: graph,
             buffers: Vec::new(),
             images: Vec::new(),
+            views: Vec::new(),
+            side_effects: false,
+            secondary_buffers: 0,
+            name: name.map(String::from),
             pass: pass,
         }
     }
 
-    pub fn create_buffer(&mut self, usage: buffer::Usage) -> BufferRef {
+    // Records the pass into `count` secondary command buffers instead of inline, so it can split its
+    // drawing between threads. 0 records inline again.
+    pub fn secondary_buffers(&mut self, count: usize) {
+        self.secondary_buffers = count;
+    }
+
+    // Keeps the pass from being culled when nothing uses what it produces, for passes that write to
+    // imported resources through other means, issue queries or present.
+    pub fn side_effects(&mut self, enabled: bool) {
+        self.side_effects = enabled;
+    }
+
+    // The pass being built, for other passes to depend on.
+    pub fn pass(&self) -> PassRef {
+        self.pass
+    }
+
+    // Runs this pass after `pass` even though it uses nothing `pass` produces, for dependencies the graph
+    // can't see, such as on queries or CPU readbacks.
+    pub fn depends_on(&mut self, pass: PassRef) {
+        add_order_edge(self.graph, self.pass, self.name.as_ref().map(String::as_str), pass);
+    }
+
+    pub fn create_buffer(&mut self, info: BufferCreateInfo, name: Option<&str>) -> BufferRef {
         self.buffers.push(BufferResource {
-            usage: usage,
+            info: info,
+            range: None,
+            name: name.map(String::from),
         });
         BufferRef(self.pass, self.buffers.len() - 1)
     }
 
     pub fn read_buffer(&mut self, buffer: BufferRef) {
-        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::ReadBuffer(buffer.1));
+        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::ReadBuffer(buffer.1, None));
+    }
+
+    // Reads only the bytes in `range`, so the pass doesn't wait on passes that wrote elsewhere in the buffer
+    // since they were last written.
+    pub fn read_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) {
+        let source = self.graph.buffer_range_source(buffer, &range);
+        self.graph.graph.add_edge(self.pass.0, (source.0).0, PassDependency::ReadBuffer(source.1, Some(range)));
     }
 
+    // The new version keeps the name of the old one.
     pub fn write_buffer(&mut self, buffer: BufferRef) -> BufferRef {
-        let new = self.create_buffer(self.graph.get_buffer(buffer).usage);
+        let (info, name) = {
+            let old = self.graph.get_buffer(buffer);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_buffer(info, name.as_ref().map(String::as_str));
         self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::CopyBuffer(buffer.1, new.1));
         new
     }
 
-    pub fn create_image(&mut self, info: ImageCreateInfo) -> ImageRef {
+    // Writes only the bytes in `range`, leaving the rest as they were in `buffer`.
+    pub fn write_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) -> BufferRef {
+        let new = self.write_buffer(buffer);
+        self.buffers[new.1].range = Some(range);
+        new
+    }
+
+    pub fn create_image(&mut self, info: ImageCreateInfo, name: Option<&str>) -> ImageRef {
         self.images.push(ImageResource {
             info: info,
             write_type: ImageWrite::Transfer,
+            range: None,
+            name: name.map(String::from),
         });
         ImageRef(self.pass, self.images.len() - 1)
     }
@@ -68,12 +124,23 @@ impl<'g, 'p, B: Backend> GraphicsPassBuilder<'g, 'p, B> {
     pub fn read_image(&mut self, image: ImageRef) {
         self.graph.graph.add_edge(
             self.pass.0, (image.0).0, 
-            PassDependency::ReadImage(image.1, ImageRead::Transfer)
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, None)
+        );
+    }
+
+    pub fn read_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) {
+        self.graph.graph.add_edge(
+            self.pass.0, (image.0).0, 
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, Some(range))
         );
     }
 
     pub fn write_image(&mut self, image: ImageRef) -> ImageRef {
-        let new = self.create_image(self.graph.get_image(image).info);
+        let (info, name) = {
+            let old = self.graph.get_image(image);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_image(info, name.as_ref().map(String::as_str));
         self.graph.graph.add_edge(
             self.pass.0, (image.0).0, 
             PassDependency::CopyImage(image.1, new.1)
@@ -81,97 +148,328 @@ impl<'g, 'p, B: Backend> GraphicsPassBuilder<'g, 'p, B> {
         new
     }
 
-    pub fn framebuffer<'b>(&'b mut self) -> FramebufferBuilder<'b, 'g, 'p, B> {
+    // Writes only `range`, leaving the other subresources as they were in `image`. Only the written range
+    // is transitioned, so passes can fill in one mip level or layer at a time.
+    pub fn write_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) -> ImageRef {
+        let new = self.write_image(image);
+        self.images[new.1].range = Some(range);
+        new
+    }
+
+    // Views `image` in `format` and with `swizzle` wherever this pass uses it, instead of in its own format.
+    // Views for every access are made by the graph and handed out by the pass's context.
+    pub fn image_view(&mut self, image: ImageRef, format: format::Format, swizzle: format::Swizzle) {
+        self.views.retain(|&(i, _, _)| i != image);
+        self.views.push((image, format, swizzle));
+    }
+
+    pub fn framebuffer<'b>(&'b mut self) -> FramebufferBuilder<'b, 'g, 'p, B, D> {
         FramebufferBuilder(self)
     }
     
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct AttachmentInfo {
     pub format: Option<format::Format>,
     pub samples: image::NumSamples,
 }
 
-pub struct FramebufferBuilder<'b, 'g, 'p, B: Backend>(&'b mut GraphicsPassBuilder<'g, 'p, B>);
+pub struct FramebufferBuilder<'b, 'g, 'p, B: Backend, D = ()>(&'b mut GraphicsPassBuilder<'g, 'p, B, D>);
 
-impl<'b, 'g, 'p, B: Backend> FramebufferBuilder<'b, 'g, 'p, B> {
-    pub fn create_attachment(&mut self, image_info: ImageCreateInfo, attachment_info: AttachmentInfo, clear: bool) -> ImageRef {
-        let image = self.0.create_image(image_info);
+impl<'b, 'g, 'p, B: Backend, D> FramebufferBuilder<'b, 'g, 'p, B, D> {
+    pub fn create_attachment(
+        &mut self,
+        image_info: ImageCreateInfo,
+        attachment_info: AttachmentInfo,
+        clear: bool,
+        name: Option<&str>,
+    ) -> ImageRef {
+        let image = self.0.create_image(image_info, name);
+        self.0.images[image.1].write_type = ImageWrite::Attachment(attachment_info, clear);
         image
     }
 
     pub fn read_attachment(&mut self, image: ImageRef, info: AttachmentInfo) {
         self.0.graph.graph.add_edge(
             self.0.pass.0, (image.0).0, 
-            PassDependency::ReadImage(image.1, ImageRead::Attachment(info))
+            PassDependency::ReadImage(image.1, ImageRead::Attachment(info), None)
+        );
+    }
+
+    pub fn read_attachment_range(&mut self, image: ImageRef, info: AttachmentInfo, range: image::SubresourceRange) {
+        self.0.graph.graph.add_edge(
+            self.0.pass.0, (image.0).0, 
+            PassDependency::ReadImage(image.1, ImageRead::Attachment(info), Some(range))
         );
     }
 
     pub fn write_attachment(&mut self, image: ImageRef, info: AttachmentInfo, clear: bool) -> ImageRef {
-        let new = self.create_attachment(self.0.graph.get_image(image).info, info, clear);
+        let (image_info, name) = {
+            let old = self.0.graph.get_image(image);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_attachment(image_info, info, clear, name.as_ref().map(String::as_str));
         self.0.graph.graph.add_edge(
             self.0.pass.0, (image.0).0, 
             PassDependency::CopyImage(image.1, new.1)
         );
         new
     }
+
+    // Renders to the first mip level in `range`, and to its layers.
+    pub fn write_attachment_range(
+        &mut self,
+        image: ImageRef,
+        info: AttachmentInfo,
+        clear: bool,
+        range: image::SubresourceRange,
+    ) -> ImageRef {
+        let new = self.write_attachment(image, info, clear);
+        self.0.images[new.1].range = Some(range);
+        new
+    }
 }
 
 
-pub trait BuildComputePass<B: Backend> {
+pub trait BuildComputePass<B: Backend, D = ()> {
     type Output;
-    type Pass: ComputePass<B>;
-    fn build(&self, builder: &mut ComputePassBuilder<B>) -> (Self::Output, Self::Pass);
+    type Pass: ComputePass<B, D>;
+    fn build(&self, builder: &mut ComputePassBuilder<B, D>) -> (Self::Output, Self::Pass);
 }
 
-impl<T, B: Backend, O, P: ComputePass<B>> BuildComputePass<B> for T 
-    where T: Fn(&mut ComputePassBuilder<B>) -> (O, P) 
+impl<T, B: Backend, D, O, P: ComputePass<B, D>> BuildComputePass<B, D> for T 
+    where T: Fn(&mut ComputePassBuilder<B, D>) -> (O, P) 
 {
     type Output = O;
     type Pass = P;
-    fn build(&self, builder: &mut ComputePassBuilder<B>) -> (O, P) {
+    fn build(&self, builder: &mut ComputePassBuilder<B, D>) -> (O, P) {
         self(builder)
     }
 }
 
-pub struct ComputePassBuilder<'g, 'p, B: Backend> {
-    graph: &'g mut FrameGraph<'p, B>,
+pub struct ComputePassBuilder<'g, 'p, B: Backend, D = ()> {
+    graph: &'g mut FrameGraph<'p, B, D>,
     pub(crate) buffers: Vec<BufferResource>,
     pub(crate) images: Vec<ImageResource>,
+    pub(crate) views: Vec<(ImageRef, format::Format, format::Swizzle)>,
+    pub(crate) side_effects: bool,
+    pub(crate) async_compute: bool,
+    pub(crate) name: Option<String>,
     pass: PassRef,
 }
 
-impl<'g, 'p, B: Backend> ComputePassBuilder<'g, 'p, B> {
-    pub(crate) fn new(graph: &'g mut FrameGraph<'p, B>, pass: PassRef) -> Self {
+impl<'g, 'p, B: Backend, D> ComputePassBuilder<'g, 'p, B, D> {
+    pub(crate) fn new(graph: &'g mut FrameGraph<'p, B, D>, pass: PassRef, name: Option<&str>) -> Self {
         ComputePassBuilder { 
             graph: graph,
             buffers: Vec::new(),
             images: Vec::new(),
+            views: Vec::new(),
+            side_effects: false,
+            async_compute: false,
+            name: name.map(String::from),
+            pass: pass,
+        }
+    }
+
+    pub fn async_compute(&mut self, enabled: bool) {
+        self.async_compute = enabled;
+    }
+
+    pub fn side_effects(&mut self, enabled: bool) {
+        self.side_effects = enabled;
+    }
+
+    pub fn pass(&self) -> PassRef {
+        self.pass
+    }
+
+    pub fn depends_on(&mut self, pass: PassRef) {
+        add_order_edge(self.graph, self.pass, self.name.as_ref().map(String::as_str), pass);
+    }
+
+    pub fn create_buffer(&mut self, info: BufferCreateInfo, name: Option<&str>) -> BufferRef {
+        self.buffers.push(BufferResource {
+            info: info,
+            range: None,
+            name: name.map(String::from),
+        });
+        BufferRef(self.pass, self.buffers.len() - 1)
+    }
+
+    pub fn read_buffer(&mut self, buffer: BufferRef) {
+        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::ReadBuffer(buffer.1, None));
+    }
+
+    pub fn read_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) {
+        let source = self.graph.buffer_range_source(buffer, &range);
+        self.graph.graph.add_edge(self.pass.0, (source.0).0, PassDependency::ReadBuffer(source.1, Some(range)));
+    }
+
+    pub fn write_buffer(&mut self, buffer: BufferRef) -> BufferRef {
+        let (info, name) = {
+            let old = self.graph.get_buffer(buffer);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_buffer(info, name.as_ref().map(String::as_str));
+        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::CopyBuffer(buffer.1, new.1));
+        new
+    }
+
+    pub fn write_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) -> BufferRef {
+        let new = self.write_buffer(buffer);
+        self.buffers[new.1].range = Some(range);
+        new
+    }
+
+    pub fn create_image(&mut self, info: ImageCreateInfo, name: Option<&str>) -> ImageRef {
+        self.images.push(ImageResource {
+            info: info,
+            write_type: ImageWrite::Transfer,
+            range: None,
+            name: name.map(String::from),
+        });
+        ImageRef(self.pass, self.images.len() - 1)
+    }
+
+    pub fn read_image(&mut self, image: ImageRef) {
+        self.graph.graph.add_edge(
+            self.pass.0, (image.0).0, 
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, None)
+        );
+    }
+
+    pub fn read_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) {
+        self.graph.graph.add_edge(
+            self.pass.0, (image.0).0, 
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, Some(range))
+        );
+    }
+
+    pub fn write_image(&mut self, image: ImageRef) -> ImageRef {
+        let (info, name) = {
+            let old = self.graph.get_image(image);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_image(info, name.as_ref().map(String::as_str));
+        self.graph.graph.add_edge(
+            self.pass.0, (image.0).0, 
+            PassDependency::CopyImage(image.1, new.1)
+        );
+        new
+    }
+
+    pub fn write_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) -> ImageRef {
+        let new = self.write_image(image);
+        self.images[new.1].range = Some(range);
+        new
+    }
+
+    pub fn image_view(&mut self, image: ImageRef, format: format::Format, swizzle: format::Swizzle) {
+        self.views.retain(|&(i, _, _)| i != image);
+        self.views.push((image, format, swizzle));
+    }
+}
+
+
+pub trait BuildTransferPass<B: Backend, D = ()> {
+    type Output;
+    type Pass: TransferPass<B, D>;
+    fn build(&self, builder: &mut TransferPassBuilder<B, D>) -> (Self::Output, Self::Pass);
+}
+
+impl<T, B: Backend, D, O, P: TransferPass<B, D>> BuildTransferPass<B, D> for T 
+    where T: Fn(&mut TransferPassBuilder<B, D>) -> (O, P) 
+{
+    type Output = O;
+    type Pass = P;
+    fn build(&self, builder: &mut TransferPassBuilder<B, D>) -> (O, P) {
+        self(builder)
+    }
+}
+
+pub struct TransferPassBuilder<'g, 'p, B: Backend, D = ()> {
+    graph: &'g mut FrameGraph<'p, B, D>,
+    pub(crate) buffers: Vec<BufferResource>,
+    pub(crate) images: Vec<ImageResource>,
+    pub(crate) side_effects: bool,
+    pub(crate) blits: bool,
+    pub(crate) name: Option<String>,
+    pass: PassRef,
+}
+
+impl<'g, 'p, B: Backend, D> TransferPassBuilder<'g, 'p, B, D> {
+    pub(crate) fn new(graph: &'g mut FrameGraph<'p, B, D>, pass: PassRef, name: Option<&str>) -> Self {
+        TransferPassBuilder { 
+            graph: graph,
+            buffers: Vec::new(),
+            images: Vec::new(),
+            side_effects: false,
+            blits: false,
+            name: name.map(String::from),
             pass: pass,
         }
     }
 
-    pub fn create_buffer(&mut self, usage: buffer::Usage) -> BufferRef {
+    pub fn side_effects(&mut self, enabled: bool) {
+        self.side_effects = enabled;
+    }
+
+    // Transfer queues can only copy and fill, so a pass that blits or clears images has to say so to be
+    // run on the graphics queue instead.
+    pub fn blits(&mut self, enabled: bool) {
+        self.blits = enabled;
+    }
+
+    pub fn pass(&self) -> PassRef {
+        self.pass
+    }
+
+    pub fn depends_on(&mut self, pass: PassRef) {
+        add_order_edge(self.graph, self.pass, self.name.as_ref().map(String::as_str), pass);
+    }
+
+    pub fn create_buffer(&mut self, info: BufferCreateInfo, name: Option<&str>) -> BufferRef {
         self.buffers.push(BufferResource {
-            usage: usage,
+            info: info,
+            range: None,
+            name: name.map(String::from),
         });
         BufferRef(self.pass, self.buffers.len() - 1)
     }
 
     pub fn read_buffer(&mut self, buffer: BufferRef) {
-        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::ReadBuffer(buffer.1));
+        self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::ReadBuffer(buffer.1, None));
+    }
+
+    pub fn read_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) {
+        let source = self.graph.buffer_range_source(buffer, &range);
+        self.graph.graph.add_edge(self.pass.0, (source.0).0, PassDependency::ReadBuffer(source.1, Some(range)));
     }
 
     pub fn write_buffer(&mut self, buffer: BufferRef) -> BufferRef {
-        let new = self.create_buffer(self.graph.get_buffer(buffer).usage);
+        let (info, name) = {
+            let old = self.graph.get_buffer(buffer);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_buffer(info, name.as_ref().map(String::as_str));
         self.graph.graph.add_edge(self.pass.0, (buffer.0).0, PassDependency::CopyBuffer(buffer.1, new.1));
         new
     }
 
-    pub fn create_image(&mut self, info: ImageCreateInfo) -> ImageRef {
+    pub fn write_buffer_range(&mut self, buffer: BufferRef, range: Range<u64>) -> BufferRef {
+        let new = self.write_buffer(buffer);
+        self.buffers[new.1].range = Some(range);
+        new
+    }
+
+    pub fn create_image(&mut self, info: ImageCreateInfo, name: Option<&str>) -> ImageRef {
         self.images.push(ImageResource {
             info: info,
             write_type: ImageWrite::Transfer,
+            range: None,
+            name: name.map(String::from),
         });
         ImageRef(self.pass, self.images.len() - 1)
     }
@@ -179,16 +477,42 @@ impl<'g, 'p, B: Backend> ComputePassBuilder<'g, 'p, B> {
     pub fn read_image(&mut self, image: ImageRef) {
         self.graph.graph.add_edge(
             self.pass.0, (image.0).0, 
-            PassDependency::ReadImage(image.1, ImageRead::Transfer)
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, None)
+        );
+    }
+
+    pub fn read_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) {
+        self.graph.graph.add_edge(
+            self.pass.0, (image.0).0, 
+            PassDependency::ReadImage(image.1, ImageRead::Transfer, Some(range))
         );
     }
 
     pub fn write_image(&mut self, image: ImageRef) -> ImageRef {
-        let new = self.create_image(self.graph.get_image(image).info);
+        let (info, name) = {
+            let old = self.graph.get_image(image);
+            (old.info, old.name.clone())
+        };
+        let new = self.create_image(info, name.as_ref().map(String::as_str));
         self.graph.graph.add_edge(
             self.pass.0, (image.0).0, 
             PassDependency::CopyImage(image.1, new.1)
         );
         new
     }
+
+    pub fn write_image_range(&mut self, image: ImageRef, range: image::SubresourceRange) -> ImageRef {
+        let new = self.write_image(image);
+        self.images[new.1].range = Some(range);
+        new
+    }
+}
+
+// Nothing can depend on a pass before it's built, so the only edge that can make a cycle here is a pass
+// depending on itself.
+fn add_order_edge<B: Backend, D>(graph: &mut FrameGraph<B, D>, pass: PassRef, name: Option<&str>, producer: PassRef) {
+    if graph.graph.add_edge(pass.0, producer.0, PassDependency::Order).is_err() {
+        let producer = if producer == pass { "itself".to_string() } else { graph.describe_pass(producer) };
+        panic!("{} can't depend on {}", pass_description(pass, name), producer);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,111 @@
+use daggy::petgraph::visit::EdgeRef;
+
+use gfx_hal::Backend;
+
+use crate::graph::{
+    FrameGraph, PassRef, QueueType, BufferRef, ImageRef, CullReason, ScheduleGraph, ScheduleMetrics, Scheduler,
+    internal::{FrameGraphInternals, PassDependency, RenderPassKind},
+};
+
+// A dependency between passes on different queues, which the consumer waits for on a semaphore the
+// producer signals. The listed resources are those handed from one queue to the other along it.
+#[derive(Clone)]
+pub struct QueueTransfer {
+    pub producer: PassRef,
+    pub consumer: PassRef,
+    pub src: QueueType,
+    pub dst: QueueType,
+    pub buffers: Vec<BufferRef>,
+    pub images: Vec<ImageRef>,
+}
+
+pub struct CompiledGraph<'g, 'p: 'g, B: Backend, D = ()> {
+    graph: &'g FrameGraph<'p, B, D>,
+    dependencies: ScheduleGraph,
+    schedule: Vec<PassRef>,
+    culled: Vec<(PassRef, CullReason)>,
+    queue_transfers: Vec<QueueTransfer>,
+}
+
+impl<'g, 'p, B: Backend, D> CompiledGraph<'g, 'p, B, D> {
+    pub(crate) fn new(graph: &'g FrameGraph<'p, B, D>, scheduler: &dyn Scheduler) -> Self {
+        let dependencies = ScheduleGraph::new(graph);
+        let mut schedule = scheduler.schedule(&dependencies);
+        assert!(dependencies.is_valid_schedule(&schedule), "Scheduler produced an invalid pass order!");
+        // Everything a live pass depends on is live too, so what remains of the schedule is still valid.
+        let live = graph.live_passes();
+        schedule.retain(|pass| live[(pass.0).index()]);
+        CompiledGraph {
+            graph: graph,
+            dependencies: dependencies,
+            schedule: schedule,
+            culled: graph.cull(&live),
+            queue_transfers: queue_transfers(graph, &live),
+        }
+    }
+
+    pub fn graph(&self) -> &'g FrameGraph<'p, B, D> {
+        self.graph
+    }
+
+    pub fn schedule(&self) -> &[PassRef] {
+        &self.schedule
+    }
+
+    pub fn culled(&self) -> &[(PassRef, CullReason)] {
+        &self.culled
+    }
+
+    pub fn queue_transfers(&self) -> &[QueueTransfer] {
+        &self.queue_transfers
+    }
+
+    pub fn metrics(&self) -> ScheduleMetrics {
+        self.dependencies.metrics(&self.schedule)
+    }
+}
+
+fn queue_transfers<B: Backend, D>(graph: &FrameGraph<B, D>, live: &[bool]) -> Vec<QueueTransfer> {
+    let mut transfers: Vec<QueueTransfer> = Vec::new();
+    for edge in graph.graph.graph().edge_references() {
+        let (consumer, producer) = (PassRef(edge.source()), PassRef(edge.target()));
+        if !live[(consumer.0).index()] {
+            continue
+        }
+        match graph.get_pass(producer).kind {
+            RenderPassKind::Import | RenderPassKind::History => continue,
+            _ => (),
+        }
+        let (src, dst) = (graph.get_pass(producer).queue, graph.get_pass(consumer).queue);
+        if src == dst {
+            continue
+        }
+
+        let index = match transfers.iter().position(|t| t.producer == producer && t.consumer == consumer) {
+            Some(index) => index,
+            None => {
+                transfers.push(QueueTransfer {
+                    producer: producer,
+                    consumer: consumer,
+                    src: src,
+                    dst: dst,
+                    buffers: Vec::new(),
+                    images: Vec::new(),
+                });
+                transfers.len() - 1
+            }
+        };
+
+        match *edge.weight() {
+            PassDependency::ReadBuffer(b, _) | PassDependency::CopyBuffer(b, _) => {
+                transfers[index].buffers.push(BufferRef(producer, b))
+            },
+            PassDependency::ReadImage(i, ..) | PassDependency::CopyImage(i, _) => {
+                transfers[index].images.push(ImageRef(producer, i))
+            },
+            // Still needs the semaphore, just nothing to transfer.
+            PassDependency::Order => (),
+        }
+    }
+    transfers
+}
\ No newline at end of file
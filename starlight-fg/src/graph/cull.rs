@@ -0,0 +1,75 @@
+use std::mem;
+
+use daggy::petgraph::Direction;
+
+use gfx_hal::Backend;
+
+use crate::graph::{FrameGraph, PassRef, internal::{FrameGraphInternals, PassDependency, RenderPassKind}};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum CullReason {
+    // Nothing in the graph uses what the pass produces.
+    NoConsumers,
+    // Everything using what the pass produces was culled as well.
+    ConsumersCulled,
+}
+
+impl<'p, B: Backend, D> FrameGraph<'p, B, D> {
+    // The passes left out when the graph is compiled, and why. A pass is kept if it has side effects,
+    // writes to an imported or history resource, or produces something a kept pass depends on.
+    pub fn culled_passes(&self) -> Vec<(PassRef, CullReason)> {
+        self.cull(&self.live_passes())
+    }
+
+    pub(crate) fn cull(&self, live: &[bool]) -> Vec<(PassRef, CullReason)> {
+        let dag = self.graph.graph();
+        self.pass_refs().filter(|pass| !live[(pass.0).index()]).map(|pass| {
+            match dag.neighbors_directed(pass.0, Direction::Incoming).next() {
+                Some(_) => (pass, CullReason::ConsumersCulled),
+                None => (pass, CullReason::NoConsumers),
+            }
+        }).collect()
+    }
+
+    // Whether each pass survives culling, indexed by insertion order.
+    pub(crate) fn live_passes(&self) -> Vec<bool> {
+        let mut live = vec![false; self.graph.node_count()];
+        let mut pending = self.pass_refs().filter(|&pass| self.is_root(pass)).collect::<Vec<_>>();
+        while let Some(pass) = pending.pop() {
+            if !mem::replace(&mut live[(pass.0).index()], true) {
+                pending.extend(self.dependencies(pass).map(|(producer, _)| producer));
+            }
+        }
+        live
+    }
+
+    fn is_root(&self, pass: PassRef) -> bool {
+        let render_pass = self.get_pass(pass);
+        match render_pass.kind {
+            RenderPassKind::Import | RenderPassKind::History => return true,
+            _ => (),
+        }
+        render_pass.side_effects || self.dependencies(pass).any(|(producer, dependency)| match *dependency {
+            PassDependency::CopyBuffer(b, _) => self.is_external(producer, b, false),
+            PassDependency::CopyImage(i, _) => self.is_external(producer, i, true),
+            _ => false,
+        })
+    }
+
+    // Whether a resource is a version of one living outside the graph, which is read after it runs.
+    fn is_external(&self, pass: PassRef, index: usize, image: bool) -> bool {
+        match self.get_pass(pass).kind {
+            RenderPassKind::Import | RenderPassKind::History => return true,
+            _ => (),
+        }
+        let previous = self.dependencies(pass).filter_map(|(producer, dependency)| match *dependency {
+            PassDependency::CopyImage(i, new) if image && new == index => Some((producer, i)),
+            PassDependency::CopyBuffer(b, new) if !image && new == index => Some((producer, b)),
+            _ => None,
+        }).next();
+        match previous {
+            Some((producer, index)) => self.is_external(producer, index, image),
+            None => false,
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use gfx_hal::Backend;
+
+use crate::graph::{
+    FrameGraph, PassRef, BufferRef, ImageRef, ImageRead, ImageWrite,
+    internal::{FrameGraphInternals, PassDependency, RenderPassKind},
+};
+
+impl<'p, B: Backend, D> FrameGraph<'p, B, D> {
+    // Renders the graph in Graphviz's DOT language, with an edge from each pass to every pass using
+    // something it produced. Passes that compiling would cull are greyed out.
+    pub fn to_dot(&self) -> String {
+        let live = self.live_passes();
+        let live = |pass: PassRef| live[(pass.0).index()];
+        to_dot(self, &DotInfo { live: Some(&live), ..DotInfo::default() })
+    }
+
+    pub fn write_dot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, self.to_dot())
+    }
+}
+
+// What compilation decided about a graph, for annotating its DOT output.
+pub(crate) struct DotInfo<'a> {
+    // Whether a pass survives culling, and its position in the schedule once compiled.
+    pub live: Option<&'a dyn Fn(PassRef) -> bool>,
+    pub position: Option<&'a dyn Fn(PassRef) -> Option<usize>>,
+    // The physical resource backing a buffer or image, and whether it is shared with other resources.
+    pub buffer: Option<&'a dyn Fn(BufferRef) -> Option<(usize, bool)>>,
+    pub image: Option<&'a dyn Fn(ImageRef) -> Option<(usize, bool)>>,
+}
+
+impl<'a> Default for DotInfo<'a> {
+    fn default() -> Self {
+        DotInfo {
+            live: None,
+            position: None,
+            buffer: None,
+            image: None,
+        }
+    }
+}
+
+pub(crate) fn to_dot<B: Backend, D>(graph: &FrameGraph<B, D>, info: &DotInfo) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "digraph FrameGraph {{").unwrap();
+    writeln!(dot, "    node [shape=box, style=filled];").unwrap();
+
+    for pass_ref in graph.pass_refs() {
+        let pass = graph.get_pass(pass_ref);
+        let (kind, color) = match pass.kind {
+            RenderPassKind::Graphics(..) => ("graphics", "lightblue"),
+            RenderPassKind::Compute(_) => ("compute", "palegreen"),
+            RenderPassKind::Transfer(_) => ("transfer", "khaki"),
+            RenderPassKind::Import => ("import", "lightgrey"),
+            RenderPassKind::History => ("history", "plum"),
+        };
+
+        let mut label = format!("{}\\n{} ({:?} queue)", escape(&graph.describe_pass(pass_ref)), kind, pass.queue);
+        for (b, buffer) in pass.buffers.iter().enumerate() {
+            write!(label, "\\n{}: {} bytes", resource_label("buffer", b, &buffer.name), buffer.info.size).unwrap();
+        }
+        for (i, image) in pass.images.iter().enumerate() {
+            let extent = image.info.kind.extent();
+            write!(
+                label, "\\n{}: {}x{}x{} {:?}",
+                resource_label("image", i, &image.name), extent.width, extent.height, extent.depth, image.info.format
+            ).unwrap();
+        }
+
+        let mut style = format!("fillcolor={}", color);
+        let live = info.live.map_or(true, |live| live(pass_ref));
+        match (live, info.position.and_then(|position| position(pass_ref)), &pass.kind) {
+            (_, _, RenderPassKind::Import) | (_, _, RenderPassKind::History) | (true, None, _) => (),
+            (true, Some(position), _) => write!(label, "\\nscheduled #{}", position).unwrap(),
+            (false, ..) => style = "fillcolor=white, color=grey, fontcolor=grey, style=dashed".to_string(),
+        }
+        writeln!(dot, "    p{} [label=\"{}\", {}];", (pass_ref.0).index(), label, style).unwrap();
+    }
+
+    for consumer in graph.pass_refs() {
+        for (producer, dependency) in graph.dependencies(consumer) {
+            let (mut label, physical) = match *dependency {
+                PassDependency::ReadBuffer(b, _) => {
+                    let buffer = BufferRef(producer, b);
+                    let name = resource_label("buffer", b, &graph.get_buffer(buffer).name);
+                    (format!("{}: read", name), info.buffer.and_then(|f| f(buffer)))
+                },
+                PassDependency::ReadImage(i, ref read, _) => {
+                    let access = match *read {
+                        ImageRead::Transfer => "read",
+                        ImageRead::Attachment(_) => "attachment read",
+                    };
+                    let image = ImageRef(producer, i);
+                    let name = resource_label("image", i, &graph.get_image(image).name);
+                    (format!("{}: {}", name, access), info.image.and_then(|f| f(image)))
+                },
+                PassDependency::CopyBuffer(b, new) => {
+                    let buffer = BufferRef(producer, b);
+                    let name = resource_label("buffer", b, &graph.get_buffer(buffer).name);
+                    (format!("{} -> {}: write", name, new), info.buffer.and_then(|f| f(buffer)))
+                },
+                PassDependency::CopyImage(i, new) => {
+                    let access = match graph.get_image(ImageRef(consumer, new)).write_type {
+                        ImageWrite::Attachment(..) => "attachment write",
+                        _ => "write",
+                    };
+                    let image = ImageRef(producer, i);
+                    let name = resource_label("image", i, &graph.get_image(image).name);
+                    (format!("{} -> {}: {}", name, new, access), info.image.and_then(|f| f(image)))
+                },
+                PassDependency::Order => ("after".to_string(), None),
+            };
+
+            let mut style = String::new();
+            if let PassDependency::Order = *dependency {
+                style.push_str(", style=dashed");
+            }
+            if let Some((index, aliased)) = physical {
+                write!(label, "\\nphysical {}", index).unwrap();
+                if aliased {
+                    style.push_str(", color=red, fontcolor=red");
+                }
+            }
+            writeln!(
+                dot, "    p{} -> p{} [label=\"{}\"{}];",
+                (producer.0).index(), (consumer.0).index(), label, style
+            ).unwrap();
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn resource_label(kind: &str, index: usize, name: &Option<String>) -> String {
+    match *name {
+        Some(ref name) => format!("{} {} {}", kind, index, escape(&format!("\"{}\"", name))),
+        None => format!("{} {}", kind, index),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
\ No newline at end of file
@@ -0,0 +1,80 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+
+use daggy::petgraph::visit::EdgeRef;
+
+use gfx_hal::{format, image, Backend};
+
+use crate::graph::{
+    FrameGraph, QueueType, BufferCreateInfo, ImageCreateInfo, ImageRef, ImageWrite,
+    internal::{FrameGraphInternals, PassDependency, RenderPassKind},
+};
+
+// Everything about a graph except its pass callbacks and names. Graphs built each frame by the same code
+// have equal structures, and can share a single compiled graph.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct GraphStructure {
+    passes: Vec<PassStructure>,
+    // Consumer and producer by insertion order, with the dependency between them.
+    edges: Vec<(usize, usize, PassDependency)>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+struct PassStructure {
+    kind: PassKind,
+    queue: QueueType,
+    side_effects: bool,
+    buffers: Vec<(BufferCreateInfo, Option<Range<u64>>)>,
+    images: Vec<(ImageCreateInfo, ImageWrite, Option<image::SubresourceRange>)>,
+    views: Vec<(ImageRef, format::Format, format::Swizzle)>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum PassKind {
+    // With the number of secondary command buffers it records into.
+    Graphics(usize),
+    Compute,
+    Transfer,
+    Import,
+    History,
+}
+
+impl GraphStructure {
+    // A quick way to tell structures apart before comparing them in full.
+    pub fn digest(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<'p, B: Backend, D> FrameGraph<'p, B, D> {
+    pub fn structure(&self) -> GraphStructure {
+        let passes = self.passes().map(|pass| PassStructure {
+            kind: match pass.kind {
+                RenderPassKind::Graphics(_, secondary_buffers) => PassKind::Graphics(secondary_buffers),
+                RenderPassKind::Compute(_) => PassKind::Compute,
+                RenderPassKind::Transfer(_) => PassKind::Transfer,
+                RenderPassKind::Import => PassKind::Import,
+                RenderPassKind::History => PassKind::History,
+            },
+            queue: pass.queue,
+            side_effects: pass.side_effects,
+            buffers: pass.buffers.iter().map(|buffer| (buffer.info, buffer.range.clone())).collect(),
+            images: pass.images.iter().map(|image| (image.info, image.write_type, image.range.clone())).collect(),
+            views: pass.views.clone(),
+        }).collect();
+        let edges = self.graph.graph().edge_references().map(|edge| {
+            (edge.source().index(), edge.target().index(), edge.weight().clone())
+        }).collect();
+        GraphStructure {
+            passes: passes,
+            edges: edges,
+        }
+    }
+
+    pub fn structural_hash(&self) -> u64 {
+        self.structure().digest()
+    }
+}
\ No newline at end of file
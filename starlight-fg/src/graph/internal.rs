@@ -1,33 +1,61 @@
-use daggy::petgraph::{self, algo::{self, DfsSpace}};
-use daggy::Walker;
+use std::ops::Range;
+use std::sync::Mutex;
 
-use gfx_hal::Backend;
+use daggy::petgraph::{self, algo};
+use daggy::petgraph::visit::EdgeRef;
 
-use crate::graph::{FrameGraph, PassRef, BufferRef, BufferResource, ImageRef, ImageResource, ImageRead};
-use crate::pass::{GraphicsPass, ComputePass};
+use gfx_hal::{format, image, Backend};
+
+use crate::graph::{FrameGraph, PassRef, QueueType, BufferRef, BufferResource, ImageRef, ImageResource, ImageRead};
+use crate::pass::{GraphicsPass, ComputePass, TransferPass};
 
 use self::PassDependency::*;
 
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub enum PassDependency {
-    ReadBuffer(usize),
-    ReadImage(usize, ImageRead),
+    // Reads the given bytes, or the whole buffer if None.
+    ReadBuffer(usize, Option<Range<u64>>),
+    // Reads the given subresources, or the whole image if None.
+    ReadImage(usize, ImageRead, Option<image::SubresourceRange>),
     CopyBuffer(usize, usize),
     CopyImage(usize, usize),
+    // Only orders the consumer after the producer, without any resource passing between them.
+    Order,
+}
+
+pub struct RenderPass<'p, B: Backend, D = ()> {
+    pub(crate) buffers: Vec<BufferResource>,
+    pub(crate) images: Vec<ImageResource>,
+    // The format and swizzle to view images in, where the pass asked for other than their own.
+    pub(crate) views: Vec<(ImageRef, format::Format, format::Swizzle)>,
+    pub kind: RenderPassKind<'p, B, D>,
+    pub queue: QueueType,
+    pub name: Option<String>,
+    // Kept even when nothing uses what it produces.
+    pub side_effects: bool,
 }
 
-pub struct RenderPass<'p, B: Backend> {
-    buffers: Vec<BufferResource>,
-    images: Vec<ImageResource>,
-    pub kind: RenderPassKind<'p, B>
+// Passes are locked while they are recorded, so they can keep mutable state and still be recorded on
+// any thread.
+pub enum RenderPassKind<'p, B: Backend, D = ()> {
+    // The pass, and how many secondary command buffers it records into, or 0 if it records inline.
+    Graphics(Mutex<Box<dyn GraphicsPass<B, D> + 'p>>, usize),
+    Compute(Mutex<Box<dyn ComputePass<B, D> + 'p>>),
+    Transfer(Mutex<Box<dyn TransferPass<B, D> + 'p>>),
+    Import,
+    // Holds the previous frame's version of a resource at index 0, and this frame's at index 1.
+    History,
 }
 
-pub enum RenderPassKind<'p, B: Backend> {
-    Graphics(Box<dyn GraphicsPass<B> + 'p>),
-    Compute(Box<dyn ComputePass<B> + 'p>),
+pub(crate) fn pass_description(pass: PassRef, name: Option<&str>) -> String {
+    match name {
+        Some(name) => format!("pass \"{}\"", name),
+        None => format!("pass {}", (pass.0).index()),
+    }
 }
 
-pub trait FrameGraphInternals<'p, B: Backend> {
-    fn get_pass(&self, pass: PassRef) -> &RenderPass<B>;
+pub trait FrameGraphInternals<'p, B: Backend, D> {
+    fn get_pass(&self, pass: PassRef) -> &RenderPass<'p, B, D>;
 
     fn get_buffer(&self, buffer: BufferRef) -> &BufferResource {
         &self.get_pass(buffer.0).buffers[buffer.1]
@@ -37,77 +65,133 @@ pub trait FrameGraphInternals<'p, B: Backend> {
         &self.get_pass(image.0).images[image.1]
     }
 
-    fn buffer_lifetime_overlap_forward(&self, b1: BufferRef, b2: BufferRef) -> bool;
+    // How a pass or resource is referred to in errors and dumps, by name when it has one.
+    fn describe_pass(&self, pass: PassRef) -> String {
+        pass_description(pass, self.get_pass(pass).name.as_ref().map(String::as_str))
+    }
 
-    fn buffer_lifetime_overlap_backward(&self, b1: BufferRef, b2: BufferRef) -> bool {
-        self.buffer_lifetime_overlap_forward(b2, b1)
+    fn describe_buffer(&self, buffer: BufferRef) -> String {
+        match self.get_buffer(buffer).name {
+            Some(ref name) => format!("buffer \"{}\"", name),
+            None => format!("buffer {} of {}", buffer.1, self.describe_pass(buffer.0)),
+        }
     }
 
-    fn image_lifetime_overlap_forward(&self, i1: ImageRef, i2: ImageRef) -> bool;
+    fn describe_image(&self, image: ImageRef) -> String {
+        match self.get_image(image).name {
+            Some(ref name) => format!("image \"{}\"", name),
+            None => format!("image {} of {}", image.1, self.describe_pass(image.0)),
+        }
+    }
+
+    // The earliest version of `buffer` whose contents in `range` are the same, skipping versions that only
+    // wrote to other bytes, so reading `range` doesn't wait on them. Later writes to `range` still wait on the
+    // read, since `buffer_readers` looks back through the versions it skipped.
+    fn buffer_range_source(&self, buffer: BufferRef, range: &Range<u64>) -> BufferRef {
+        let mut buffer = buffer;
+        loop {
+            match self.get_buffer(buffer).range {
+                Some(ref written) if written.start >= range.end || range.start >= written.end => (),
+                _ => return buffer,
+            }
+            match self.previous_buffer(buffer) {
+                Some(previous) => buffer = previous,
+                None => return buffer,
+            }
+        }
+    }
 
-    fn image_lifetime_overlap_backward(&self, i1: ImageRef, i2: ImageRef) -> bool {
-        self.image_lifetime_overlap_forward(i2, i1)
+    // The version `buffer` was written from, if it isn't the first.
+    fn previous_buffer(&self, buffer: BufferRef) -> Option<BufferRef> {
+        self.dependencies(buffer.0).filter_map(|(producer, dependency)| match *dependency {
+            CopyBuffer(old, new) if new == buffer.1 => Some(BufferRef(producer, old)),
+            _ => None,
+        }).next()
     }
 
+    // Every version of a buffer lives in the same memory, so writing `range` of the version after `buffer`
+    // has to wait for the passes returned here: those reading an overlapping range of `buffer`, or of an
+    // earlier version that nothing has written over since.
+    fn buffer_readers(&self, buffer: BufferRef, range: &Option<Range<u64>>) -> Vec<PassRef>;
+
+    // Images are only read at the version they were asked for, so the next version waits on just these.
+    fn image_readers(&self, image: ImageRef) -> Vec<PassRef>;
+
+    // Whether `pass` depends on `other`, directly or through other passes.
+    fn runs_after(&self, pass: PassRef, other: PassRef) -> bool;
+
     //TODO: Make these impl Iterator as soon as available in traits.
 
     fn pass_refs(&self) -> Box<dyn Iterator<Item=PassRef>>; 
 
-    fn passes<'a>(&'a self) -> Box<dyn Iterator<Item=&'a RenderPass<'a, B>> + 'a>;
+    fn passes<'a>(&'a self) -> Box<dyn Iterator<Item=&'a RenderPass<'p, B, D>> + 'a> where 'p: 'a;
+
+    // The producers of everything `pass` uses, along with how it uses them.
+    fn dependencies<'a>(&'a self, pass: PassRef) -> Box<dyn Iterator<Item=(PassRef, &'a PassDependency)> + 'a>;
 }
 
-impl<'p, B: Backend> FrameGraphInternals<'p, B> for FrameGraph<'p, B> {
-    fn get_pass(&self, pass: PassRef) -> &RenderPass<B> {
-        &self.graph[pass.0].unwrap()
+impl<'p, B: Backend, D> FrameGraphInternals<'p, B, D> for FrameGraph<'p, B, D> {
+    fn get_pass(&self, pass: PassRef) -> &RenderPass<'p, B, D> {
+        self.graph[pass.0].as_ref().unwrap()
     }
 
-    fn buffer_lifetime_overlap_forward(&self, b1: BufferRef, b2: BufferRef) -> bool {
-        let dfs_space = DfsSpace::new(self.graph.graph());
-        for (_, reader) in self.graph.children((b1.0).0)
-            .iter(&self.graph)
-            .filter(|(e, _)| { 
-                match self.graph[*e] {
-                    ReadBuffer(b) | CopyBuffer(b, _) if b == b1.1 => true,
-                    _ => false,
+    fn buffer_readers(&self, buffer: BufferRef, range: &Option<Range<u64>>) -> Vec<PassRef> {
+        let overlaps = |a: &Option<Range<u64>>, b: &Option<Range<u64>>| match (a, b) {
+            (Some(a), Some(b)) => a.start < b.end && b.start < a.end,
+            _ => true,
+        };
+        let mut readers = Vec::new();
+        let mut written = Vec::new();
+        let mut version = Some(buffer);
+        while let Some(buffer) = version {
+            for edge in self.graph.graph().edges_directed((buffer.0).0, petgraph::Direction::Incoming) {
+                match *edge.weight() {
+                    ReadBuffer(b, ref read) if b == buffer.1 && overlaps(read, range) => {
+                        if !written.iter().any(|w| overlaps(w, read)) && !readers.contains(&PassRef(edge.source())) {
+                            readers.push(PassRef(edge.source()));
+                        }
+                    },
+                    _ => (),
                 }
-            }) 
-        {
-            if algo::has_path_connecting(self.graph.graph(), (b2.0).0, reader, Some(&mut dfs_space)) {
-                return true
             }
+            written.push(self.get_buffer(buffer).range.clone());
+            version = self.previous_buffer(buffer);
         }
-        false
+        readers
     }
 
-    fn image_lifetime_overlap_forward(&self, i1: ImageRef, i2: ImageRef) -> bool {
-        let dfs_space = DfsSpace::new(self.graph.graph());
-        for (_, reader) in self.graph.children((i1.0).0)
-            .iter(&self.graph)
-            .filter(|(e, _)| { 
-                match self.graph[*e] {
-                    ReadImage(i, _) | CopyImage(i, _) if i == i1.1 => true,
-                    _ => false,
-                }
-            }) 
-        {
-            if algo::has_path_connecting(self.graph.graph(), (i2.0).0, reader, Some(&mut dfs_space)) {
-                return true
+    fn image_readers(&self, image: ImageRef) -> Vec<PassRef> {
+        let mut readers = Vec::new();
+        for edge in self.graph.graph().edges_directed((image.0).0, petgraph::Direction::Incoming) {
+            match *edge.weight() {
+                ReadImage(i, ..) if i == image.1 && !readers.contains(&PassRef(edge.source())) => {
+                    readers.push(PassRef(edge.source()))
+                },
+                _ => (),
             }
         }
-        false
+        readers
+    }
+
+    fn runs_after(&self, pass: PassRef, other: PassRef) -> bool {
+        algo::has_path_connecting(self.graph.graph(), pass.0, other.0, None)
     }
 
     fn pass_refs(&self) -> Box<dyn Iterator<Item=PassRef>> {
         Box::new(self.graph.graph().node_indices().map(|i| PassRef(i)))
     }
 
-    fn passes<'a>(&'a self) -> Box<dyn Iterator<Item=&'a RenderPass<'a, B>> + 'a> {
+    fn passes<'a>(&'a self) -> Box<dyn Iterator<Item=&'a RenderPass<'p, B, D>> + 'a> where 'p: 'a {
         Box::new(self.graph.graph().node_indices().map(|i| self.get_pass(PassRef(i))))
     }
+
+    fn dependencies<'a>(&'a self, pass: PassRef) -> Box<dyn Iterator<Item=(PassRef, &'a PassDependency)> + 'a> {
+        Box::new(self.graph.graph().edges(pass.0).map(|e| (PassRef(e.target()), e.weight())))
+    }
 }
 
-pub(crate) trait FrameGraphInternalsMut<'p, B: Backend> {
-    fn get_pass_mut(&mut self, pass: PassRef) -> &mut RenderPass<B>;
+pub(crate) trait FrameGraphInternalsMut<'p, B: Backend, D> {
+    fn get_pass_mut(&mut self, pass: PassRef) -> &mut RenderPass<'p, B, D>;
 
     fn get_buffer_mut(&mut self, buffer: BufferRef) -> &mut BufferResource {
         &mut self.get_pass_mut(buffer.0).buffers[buffer.1]
@@ -118,8 +202,8 @@ pub(crate) trait FrameGraphInternalsMut<'p, B: Backend> {
     }
 }
 
-impl<'p, B: Backend> FrameGraphInternalsMut<'p, B> for FrameGraph<'p, B> {
-    fn get_pass_mut(&mut self, pass: PassRef) -> &mut RenderPass<B> {
-        &mut self.graph[pass.0].unwrap()
+impl<'p, B: Backend, D> FrameGraphInternalsMut<'p, B, D> for FrameGraph<'p, B, D> {
+    fn get_pass_mut(&mut self, pass: PassRef) -> &mut RenderPass<'p, B, D> {
+        self.graph[pass.0].as_mut().unwrap()
     }
 }
\ No newline at end of file
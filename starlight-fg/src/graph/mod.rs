@@ -1,74 +1,202 @@
+use std::sync::Mutex;
+
 use daggy::{self, NodeIndex};
 
-use gfx_hal::Backend;
+use gfx_hal::{image, Backend};
 
 mod build;
+mod compile;
+mod cull;
+mod dot;
+mod hash;
 mod resources;
+mod schedule;
 pub mod internal;
 
+#[cfg(test)]
+mod tests;
+
 pub use self::build::*;
+pub use self::compile::*;
+pub use self::cull::CullReason;
+pub use self::hash::GraphStructure;
 pub use self::resources::*;
+pub use self::schedule::*;
+pub(crate) use self::dot::{to_dot, DotInfo};
 use self::internal::*;
 
-#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct PassRef(NodeIndex);
 
-#[derive(Eq, PartialEq)]
-pub struct BufferRef(PassRef, usize);
-
-#[derive(Eq, PartialEq)]
-pub struct ImageRef(PassRef, usize);
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum QueueType {
+    Graphics,
+    Compute,
+    Transfer,
+}
 
 
-pub struct FrameGraph<'p, B: Backend> {
-    graph: daggy::Dag<Option<RenderPass<'p, B>>, PassDependency>,
+// `D` is the type of the user data handed to every pass when a frame is recorded.
+pub struct FrameGraph<'p, B: Backend, D = ()> {
+    graph: daggy::Dag<Option<RenderPass<'p, B, D>>, PassDependency>,
 }
 
-impl<'p, B: Backend> Default for FrameGraph<'p, B> {
-    fn default() -> FrameGraph<'p, B> {
+impl<'p, B: Backend, D> Default for FrameGraph<'p, B, D> {
+    fn default() -> FrameGraph<'p, B, D> {
         FrameGraph {
             graph: Default::default(),
         }
     }
 }
 
-impl<'p, B: Backend> FrameGraph<'p, B> {
-    pub fn new() -> FrameGraph<'p, B> {
+impl<'p, B: Backend, D> FrameGraph<'p, B, D> {
+    pub fn new() -> FrameGraph<'p, B, D> {
         Default::default()
     }
 
-    pub fn add_graphics_pass<T: BuildGraphicsPass<B>>(&mut self, build: T) -> T::Output
+    pub fn compile(&self) -> CompiledGraph<'_, 'p, B, D> {
+        self.compile_with(&InsertionOrder)
+    }
+
+    pub fn compile_with(&self, scheduler: &dyn Scheduler) -> CompiledGraph<'_, 'p, B, D> {
+        CompiledGraph::new(self, scheduler)
+    }
+
+    pub fn pass_name(&self, pass: PassRef) -> Option<&str> {
+        self.get_pass(pass).name.as_ref().map(String::as_str)
+    }
+
+    pub fn buffer_name(&self, buffer: BufferRef) -> Option<&str> {
+        self.get_buffer(buffer).name.as_ref().map(String::as_str)
+    }
+
+    pub fn image_name(&self, image: ImageRef) -> Option<&str> {
+        self.get_image(image).name.as_ref().map(String::as_str)
+    }
+
+    pub fn import_buffer(&mut self, info: BufferCreateInfo, name: Option<&str>) -> BufferRef {
+        let pass_ref = PassRef(self.graph.add_node(Some(RenderPass {
+            buffers: vec![BufferResource { info: info, range: None, name: name.map(String::from) }],
+            images: Vec::new(),
+            views: Vec::new(),
+            kind: RenderPassKind::Import,
+            queue: QueueType::Graphics,
+            name: None,
+            side_effects: false,
+        })));
+        BufferRef(pass_ref, 0)
+    }
+
+    // `layout` is the layout the image is in when the frame begins. It is returned to this layout after
+    // its last use in the frame.
+    pub fn import_image(&mut self, info: ImageCreateInfo, layout: image::Layout, name: Option<&str>) -> ImageRef {
+        let pass_ref = PassRef(self.graph.add_node(Some(RenderPass {
+            buffers: Vec::new(),
+            images: vec![ImageResource { info: info, write_type: ImageWrite::Import(layout), range: None, name: name.map(String::from) }],
+            views: Vec::new(),
+            kind: RenderPassKind::Import,
+            queue: QueueType::Graphics,
+            name: None,
+            side_effects: false,
+        })));
+        ImageRef(pass_ref, 0)
+    }
+
+    // History resources have no contents on the first frame they are rendered, which passes can check
+    // with `history_valid` on their context.
+    pub fn create_history_buffer(&mut self, info: BufferCreateInfo, name: Option<&str>) -> HistoryBuffer {
+        let pass_ref = PassRef(self.graph.add_node(Some(RenderPass {
+            buffers: vec![
+                BufferResource { info: info, range: None, name: name.map(|name| format!("{} (previous)", name)) },
+                BufferResource { info: info, range: None, name: name.map(String::from) },
+            ],
+            images: Vec::new(),
+            views: Vec::new(),
+            kind: RenderPassKind::History,
+            queue: QueueType::Graphics,
+            name: None,
+            side_effects: false,
+        })));
+        HistoryBuffer { previous: BufferRef(pass_ref, 0), current: BufferRef(pass_ref, 1) }
+    }
+
+    pub fn create_history_image(&mut self, info: ImageCreateInfo, name: Option<&str>) -> HistoryImage {
+        let pass_ref = PassRef(self.graph.add_node(Some(RenderPass {
+            buffers: Vec::new(),
+            images: vec![
+                ImageResource {
+                    info: info,
+                    write_type: ImageWrite::History,
+                    range: None,
+                    name: name.map(|name| format!("{} (previous)", name)),
+                },
+                ImageResource { info: info, write_type: ImageWrite::History, range: None, name: name.map(String::from) },
+            ],
+            views: Vec::new(),
+            kind: RenderPassKind::History,
+            queue: QueueType::Graphics,
+            name: None,
+            side_effects: false,
+        })));
+        HistoryImage { previous: ImageRef(pass_ref, 0), current: ImageRef(pass_ref, 1) }
+    }
+
+    pub fn add_graphics_pass<T: BuildGraphicsPass<B, D>>(&mut self, name: Option<&str>, build: T) -> T::Output
         where T::Pass: 'p, 
     {
         let pass_ref = PassRef(self.graph.add_node(None));
-        let mut builder = GraphicsPassBuilder::new(self, pass_ref);
+        let mut builder = GraphicsPassBuilder::new(self, pass_ref, name);
         let (output, pass) = build.build(&mut builder);
         *self.graph.node_weight_mut(pass_ref.0).unwrap() = Some(RenderPass {
             buffers: builder.buffers,
             images: builder.images,
-            kind: RenderPassKind::Graphics(Box::new(pass))
+            views: builder.views,
+            kind: RenderPassKind::Graphics(Mutex::new(Box::new(pass)), builder.secondary_buffers),
+            queue: QueueType::Graphics,
+            name: builder.name,
+            side_effects: builder.side_effects,
         });
         output
     }
 
-    pub fn add_compute_pass<T: BuildComputePass<B>>(&mut self, build: T) -> T::Output
+    pub fn add_compute_pass<T: BuildComputePass<B, D>>(&mut self, name: Option<&str>, build: T) -> T::Output
         where T::Pass: 'p,
     {
         let pass_ref = PassRef(self.graph.add_node(None));
-        let mut builder = ComputePassBuilder::new(self, pass_ref);
+        let mut builder = ComputePassBuilder::new(self, pass_ref, name);
         let (output, pass) = build.build(&mut builder);
         *self.graph.node_weight_mut(pass_ref.0).unwrap() = Some(RenderPass {
             buffers: builder.buffers,
             images: builder.images,
-            kind: RenderPassKind::Compute(Box::new(pass))
+            views: builder.views,
+            kind: RenderPassKind::Compute(Mutex::new(Box::new(pass))),
+            queue: if builder.async_compute { QueueType::Compute } else { QueueType::Graphics },
+            name: builder.name,
+            side_effects: builder.side_effects,
         });
         output
     }
 
-    
+    pub fn add_transfer_pass<T: BuildTransferPass<B, D>>(&mut self, name: Option<&str>, build: T) -> T::Output
+        where T::Pass: 'p,
+    {
+        let pass_ref = PassRef(self.graph.add_node(None));
+        let mut builder = TransferPassBuilder::new(self, pass_ref, name);
+        let (output, pass) = build.build(&mut builder);
+        *self.graph.node_weight_mut(pass_ref.0).unwrap() = Some(RenderPass {
+            buffers: builder.buffers,
+            images: builder.images,
+            views: Vec::new(),
+            kind: RenderPassKind::Transfer(Mutex::new(Box::new(pass))),
+            queue: if builder.blits { QueueType::Graphics } else { QueueType::Transfer },
+            name: builder.name,
+            side_effects: builder.side_effects,
+        });
+        output
+    }
 }
 
-impl<'p, B: Backend> FrameGraph<'p, B> {
+impl<'p, B: Backend, D> FrameGraph<'p, B, D> {
     
 }
 
@@ -1,36 +1,69 @@
+use std::ops::Range;
+
 use gfx_hal::{buffer, image, format};
 
 use crate::graph::{PassRef, AttachmentInfo};
 
-#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct BufferRef(pub(crate) PassRef, pub(crate) usize);
 
-pub struct BufferResource {
+// A buffer kept from one frame to the next. `previous` holds what was written to `current` last frame.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HistoryBuffer {
+    pub previous: BufferRef,
+    pub current: BufferRef,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BufferCreateInfo {
+    pub size: u64,
     pub usage: buffer::Usage,
 }
 
+pub struct BufferResource {
+    pub info: BufferCreateInfo,
+    // The bytes written to make this version, or None for the whole buffer.
+    pub range: Option<Range<u64>>,
+    pub name: Option<String>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum ImageRead {
     Transfer,
     Attachment(AttachmentInfo),
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum ImageWrite {
     Transfer,
     Attachment(AttachmentInfo, bool),
+    Import(image::Layout),
+    History,
 }
 
-#[derive(Eq, PartialEq)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct ImageRef(pub(crate) PassRef, pub(crate) usize);
 
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HistoryImage {
+    pub previous: ImageRef,
+    pub current: ImageRef,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct ImageCreateInfo {
-    kind: image::Kind,
-    mip_levels: image::Level,
-    format: format::Format,
-    tiling: image::Tiling,
-    usage: image::Usage,
+    pub kind: image::Kind,
+    pub mip_levels: image::Level,
+    pub format: format::Format,
+    pub tiling: image::Tiling,
+    pub usage: image::Usage,
 }
 
 pub struct ImageResource {
     pub info: ImageCreateInfo,
     pub write_type: ImageWrite,
+    // The subresources written to make this version, or None for the whole image. The rest keep the
+    // contents of the version it was written from.
+    pub range: Option<image::SubresourceRange>,
+    pub name: Option<String>,
 }
\ No newline at end of file
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use daggy::NodeIndex;
+use daggy::petgraph::visit::EdgeRef;
+
+use gfx_hal::Backend;
+
+use crate::graph::{FrameGraph, PassRef, BufferRef, ImageRef, internal::{FrameGraphInternals, PassDependency}};
+
+pub trait Scheduler {
+    fn schedule(&self, graph: &ScheduleGraph) -> Vec<PassRef>;
+}
+
+// Runs passes in the order they were added to the graph wherever dependencies allow.
+pub struct InsertionOrder;
+
+impl Scheduler for InsertionOrder {
+    fn schedule(&self, graph: &ScheduleGraph) -> Vec<PassRef> {
+        graph.list_schedule(|_, _| 0)
+    }
+}
+
+// Prefers the ready pass that frees the most resources relative to those it creates.
+pub struct MinimizeMemory;
+
+impl Scheduler for MinimizeMemory {
+    fn schedule(&self, graph: &ScheduleGraph) -> Vec<PassRef> {
+        graph.list_schedule(|ready, positions| {
+            let (index, _) = ready.iter().enumerate().min_by_key(|&(_, &pass)| {
+                let freed = graph.passes[pass].used.iter()
+                    .filter(|&&r| graph.resources[r].consumers.iter().all(|&c| c == pass || positions[c].is_some()))
+                    .count();
+                graph.passes[pass].created.len() as isize - freed as isize
+            }).unwrap();
+            index
+        })
+    }
+}
+
+// Prefers the ready pass whose producers finished longest ago, to give barriers time to resolve.
+pub struct LatencyHiding;
+
+impl Scheduler for LatencyHiding {
+    fn schedule(&self, graph: &ScheduleGraph) -> Vec<PassRef> {
+        graph.list_schedule(|ready, positions| {
+            let (index, _) = ready.iter().enumerate().min_by_key(|&(_, &pass)| {
+                graph.passes[pass].producers.iter().map(|&p| positions[p]).max()
+            }).unwrap();
+            index
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScheduleMetrics {
+    pub peak_live_resources: usize,
+    pub min_dependency_distance: Option<usize>,
+    pub mean_dependency_distance: f32,
+}
+
+struct PassInfo {
+    producers: Vec<usize>,
+    consumers: Vec<usize>,
+    created: Vec<usize>,
+    used: Vec<usize>,
+}
+
+struct ResourceInfo {
+    producer: usize,
+    consumers: Vec<usize>,
+}
+
+// A flattened view of the dependencies in a FrameGraph, indexed by pass insertion order.
+pub struct ScheduleGraph {
+    passes: Vec<PassInfo>,
+    resources: Vec<ResourceInfo>,
+}
+
+impl ScheduleGraph {
+    pub(crate) fn new<B: Backend, D>(graph: &FrameGraph<B, D>) -> Self {
+        let dag = graph.graph.graph();
+        let mut passes = dag.node_indices().map(|_| PassInfo {
+            producers: Vec::new(),
+            consumers: Vec::new(),
+            created: Vec::new(),
+            used: Vec::new(),
+        }).collect::<Vec<_>>();
+
+        let mut resources = Vec::new();
+        let mut buffers = HashMap::new();
+        let mut images = HashMap::new();
+        for node in dag.node_indices() {
+            let pass = graph.get_pass(PassRef(node));
+            for buffer in 0..pass.buffers.len() {
+                buffers.insert((node.index(), buffer), resources.len());
+                passes[node.index()].created.push(resources.len());
+                resources.push(ResourceInfo { producer: node.index(), consumers: Vec::new() });
+            }
+            for image in 0..pass.images.len() {
+                images.insert((node.index(), image), resources.len());
+                passes[node.index()].created.push(resources.len());
+                resources.push(ResourceInfo { producer: node.index(), consumers: Vec::new() });
+            }
+        }
+
+        for edge in dag.edge_references() {
+            let (consumer, producer) = (edge.source().index(), edge.target().index());
+            let resource = match *edge.weight() {
+                PassDependency::ReadBuffer(b, _) | PassDependency::CopyBuffer(b, _) => Some(buffers[&(producer, b)]),
+                PassDependency::ReadImage(i, ..) | PassDependency::CopyImage(i, _) => Some(images[&(producer, i)]),
+                PassDependency::Order => None,
+            };
+            if let Some(resource) = resource {
+                if !resources[resource].consumers.contains(&consumer) {
+                    resources[resource].consumers.push(consumer);
+                    passes[consumer].used.push(resource);
+                }
+            }
+            if !passes[consumer].producers.contains(&producer) {
+                passes[consumer].producers.push(producer);
+                passes[producer].consumers.push(consumer);
+            }
+        }
+
+        // Writing a new version reuses the memory of the old one, so anything still reading the old
+        // contents is ordered before it. These orderings only constrain the schedule, without keeping readers
+        // alive when culling.
+        let mut written = HashMap::new();
+        for edge in dag.edge_references() {
+            let (writer, producer) = (PassRef(edge.source()), PassRef(edge.target()));
+            let (readers, resource, key) = match *edge.weight() {
+                PassDependency::CopyBuffer(b, new) => {
+                    let range = graph.get_buffer(BufferRef(writer, new)).range.clone();
+                    let buffer = BufferRef(producer, b);
+                    (graph.buffer_readers(buffer, &range), graph.describe_buffer(buffer), (producer, b, false))
+                },
+                PassDependency::CopyImage(i, _) => {
+                    let image = ImageRef(producer, i);
+                    (graph.image_readers(image), graph.describe_image(image), (producer, i, true))
+                },
+                _ => continue,
+            };
+            if let Some(other) = written.insert(key, writer) {
+                let (other, writer) = (graph.describe_pass(other), graph.describe_pass(writer));
+                panic!("{} and {} both write over {}", other, writer, resource);
+            }
+            for reader in readers.into_iter().filter(|&reader| reader != writer) {
+                assert!(
+                    !graph.runs_after(reader, writer),
+                    "{} reads {} after {} has written over it",
+                    graph.describe_pass(reader), resource, graph.describe_pass(writer)
+                );
+                let (reader, writer) = ((reader.0).index(), (writer.0).index());
+                if !passes[writer].producers.contains(&reader) {
+                    passes[writer].producers.push(reader);
+                    passes[reader].consumers.push(writer);
+                }
+            }
+        }
+
+        ScheduleGraph {
+            passes: passes,
+            resources: resources,
+        }
+    }
+
+    pub fn pass_count(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn pass_ref(&self, pass: usize) -> PassRef {
+        PassRef(NodeIndex::new(pass))
+    }
+
+    pub fn producers(&self, pass: usize) -> &[usize] {
+        &self.passes[pass].producers
+    }
+
+    pub fn consumers(&self, pass: usize) -> &[usize] {
+        &self.passes[pass].consumers
+    }
+
+    // Builds an order one pass at a time. `choose` is given the passes whose producers have all been
+    // scheduled, in insertion order, along with the position of every pass scheduled so far, and
+    // returns an index into the ready list.
+    pub fn list_schedule<F>(&self, mut choose: F) -> Vec<PassRef>
+        where F: FnMut(&[usize], &[Option<usize>]) -> usize
+    {
+        let mut pending = self.passes.iter().map(|p| p.producers.len()).collect::<Vec<_>>();
+        let mut ready = (0..self.passes.len()).filter(|&p| pending[p] == 0).collect::<Vec<_>>();
+        let mut positions = vec![None; self.passes.len()];
+
+        let mut schedule = Vec::with_capacity(self.passes.len());
+        while !ready.is_empty() {
+            let pass = ready.remove(choose(&ready, &positions));
+            positions[pass] = Some(schedule.len());
+            schedule.push(self.pass_ref(pass));
+            for &consumer in &self.passes[pass].consumers {
+                pending[consumer] -= 1;
+                if pending[consumer] == 0 {
+                    let at = ready.binary_search(&consumer).unwrap_err();
+                    ready.insert(at, consumer);
+                }
+            }
+        }
+        schedule
+    }
+
+    pub fn is_valid_schedule(&self, schedule: &[PassRef]) -> bool {
+        match self.positions(schedule).and_then(|positions| positions.into_iter().collect::<Option<Vec<_>>>()) {
+            Some(positions) => self.passes.iter().enumerate().all(|(pass, info)| {
+                info.producers.iter().all(|&p| positions[p] < positions[pass])
+            }),
+            None => false,
+        }
+    }
+
+    // Passes missing from `schedule`, such as culled ones, are left out of the metrics.
+    pub fn metrics(&self, schedule: &[PassRef]) -> ScheduleMetrics {
+        let positions = self.positions(schedule).expect("Schedule contains a pass twice!");
+
+        let mut live = vec![0isize; schedule.len() + 1];
+        for resource in &self.resources {
+            let first = match positions[resource.producer] {
+                Some(first) => first,
+                None => continue,
+            };
+            let last = resource.consumers.iter().filter_map(|&c| positions[c]).max().unwrap_or(first);
+            live[first] += 1;
+            live[last + 1] -= 1;
+        }
+        let peak_live_resources = live.iter()
+            .scan(0, |count, delta| { *count += delta; Some(*count) })
+            .max()
+            .unwrap_or(0) as usize;
+
+        let distances = self.passes.iter().enumerate()
+            .flat_map(|(pass, info)| info.producers.iter().map(move |&p| (p, pass)))
+            .filter_map(|(producer, consumer)| Some(positions[consumer]? - positions[producer]?))
+            .collect::<Vec<_>>();
+
+        ScheduleMetrics {
+            peak_live_resources: peak_live_resources,
+            min_dependency_distance: distances.iter().cloned().min(),
+            mean_dependency_distance: if distances.is_empty() {
+                0.0
+            } else {
+                distances.iter().sum::<usize>() as f32 / distances.len() as f32
+            },
+        }
+    }
+
+    fn positions(&self, schedule: &[PassRef]) -> Option<Vec<Option<usize>>> {
+        let mut positions = vec![None; self.passes.len()];
+        for (position, pass) in schedule.iter().enumerate() {
+            match positions.get_mut((pass.0).index()) {
+                Some(slot @ None) => *slot = Some(position),
+                _ => return None,
+            }
+        }
+        Some(positions)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,307 @@
+use gfx_hal::buffer;
+use gfx_backend_vulkan::Backend as Vulkan;
+
+use crate::context::ComputeContext;
+use crate::graph::{
+    FrameGraph, PassRef, BufferCreateInfo, ComputePassBuilder, CullReason,
+    Scheduler, ScheduleGraph, InsertionOrder, MinimizeMemory, LatencyHiding,
+};
+use crate::pass::PassError;
+
+// Every test graph is made of compute passes, which need no device to build or schedule.
+type Graph = FrameGraph<'static, Vulkan>;
+type Callback = fn(&mut ComputeContext<Vulkan>, &()) -> Result<(), PassError>;
+
+fn noop(_: &mut ComputeContext<Vulkan>, _: &()) -> Result<(), PassError> {
+    Ok(())
+}
+
+fn fail(_: &mut ComputeContext<Vulkan>, _: &()) -> Result<(), PassError> {
+    Err("failed".into())
+}
+
+fn pass<F, O>(callback: Callback, build: F) -> impl Fn(&mut ComputePassBuilder<Vulkan>) -> (O, Callback)
+    where F: Fn(&mut ComputePassBuilder<Vulkan>) -> O
+{
+    move |builder| (build(builder), callback)
+}
+
+fn add_pass<F, O>(graph: &mut Graph, build: F) -> O
+    where F: Fn(&mut ComputePassBuilder<Vulkan>) -> O
+{
+    graph.add_compute_pass(None, pass(noop, build))
+}
+
+fn info(size: u64) -> BufferCreateInfo {
+    BufferCreateInfo { size: size, usage: buffer::Usage::STORAGE }
+}
+
+fn schedule(graph: &Graph, scheduler: &dyn Scheduler) -> Vec<PassRef> {
+    scheduler.schedule(&ScheduleGraph::new(graph))
+}
+
+// Two independent chains, the first added in full before the second.
+fn chains() -> (Graph, [PassRef; 4]) {
+    let mut graph = Graph::new();
+    let (p0, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let (p1, c) = add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        (b.pass(), b.create_buffer(info(256), None))
+    });
+    let (p2, d) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let p3 = add_pass(&mut graph, |b| {
+        b.read_buffer(c);
+        b.read_buffer(d);
+        b.side_effects(true);
+        b.pass()
+    });
+    (graph, [p0, p1, p2, p3])
+}
+
+// Three passes creating a buffer each, where only the first buffer is read, by the last pass.
+fn fan() -> (Graph, [PassRef; 4]) {
+    let mut graph = Graph::new();
+    let (p0, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let (p1, _) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let (p2, _) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let p3 = add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        b.side_effects(true);
+        b.pass()
+    });
+    (graph, [p0, p1, p2, p3])
+}
+
+#[test]
+fn insertion_order_keeps_insertion_order() {
+    let (graph, [p0, p1, p2, p3]) = chains();
+    assert_eq!(schedule(&graph, &InsertionOrder), vec![p0, p1, p2, p3]);
+}
+
+#[test]
+fn latency_hiding_interleaves_chains() {
+    let (graph, [p0, p1, p2, p3]) = chains();
+    assert_eq!(schedule(&graph, &LatencyHiding), vec![p0, p2, p1, p3]);
+}
+
+#[test]
+fn minimize_memory_runs_consumers_early() {
+    let (graph, [p0, p1, p2, p3]) = fan();
+    assert_eq!(schedule(&graph, &MinimizeMemory), vec![p0, p3, p1, p2]);
+}
+
+#[test]
+fn schedulers_produce_valid_schedules() {
+    for &(ref graph, _) in &[chains(), fan()] {
+        let dependencies = ScheduleGraph::new(graph);
+        for scheduler in &[&InsertionOrder as &dyn Scheduler, &MinimizeMemory, &LatencyHiding] {
+            assert!(dependencies.is_valid_schedule(&scheduler.schedule(&dependencies)));
+        }
+    }
+}
+
+#[test]
+fn latency_hiding_increases_dependency_distance() {
+    let (graph, _) = chains();
+    let dependencies = ScheduleGraph::new(&graph);
+    let insertion = dependencies.metrics(&InsertionOrder.schedule(&dependencies));
+    let latency = dependencies.metrics(&LatencyHiding.schedule(&dependencies));
+
+    assert_eq!(insertion.peak_live_resources, 2);
+    assert_eq!(insertion.min_dependency_distance, Some(1));
+    assert!((insertion.mean_dependency_distance - 4.0 / 3.0).abs() < 1e-6);
+    assert_eq!(latency.peak_live_resources, 3);
+    assert_eq!(latency.min_dependency_distance, Some(1));
+    assert!((latency.mean_dependency_distance - 5.0 / 3.0).abs() < 1e-6);
+}
+
+#[test]
+fn minimize_memory_lowers_peak_live_resources() {
+    let (graph, _) = fan();
+    let dependencies = ScheduleGraph::new(&graph);
+    let insertion = dependencies.metrics(&InsertionOrder.schedule(&dependencies));
+    let memory = dependencies.metrics(&MinimizeMemory.schedule(&dependencies));
+
+    assert_eq!(insertion.peak_live_resources, 2);
+    assert_eq!(insertion.min_dependency_distance, Some(3));
+    assert_eq!(memory.peak_live_resources, 1);
+    assert_eq!(memory.min_dependency_distance, Some(1));
+}
+
+#[test]
+fn metrics_skip_passes_missing_from_schedule() {
+    let (graph, [p0, _, _, p3]) = fan();
+    let metrics = ScheduleGraph::new(&graph).metrics(&[p0, p3]);
+    assert_eq!(metrics.peak_live_resources, 1);
+    assert_eq!(metrics.min_dependency_distance, Some(1));
+}
+
+#[test]
+fn culling_keeps_roots_and_their_producers() {
+    let mut graph = Graph::new();
+    let imported = graph.import_buffer(info(256), None);
+    let unused = add_pass(&mut graph, |b| {
+        b.create_buffer(info(256), None);
+        b.pass()
+    });
+    let (producer, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let consumer = add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        b.create_buffer(info(256), None);
+        b.pass()
+    });
+    let side_effects = add_pass(&mut graph, |b| {
+        b.side_effects(true);
+        b.pass()
+    });
+    let (export, _) = add_pass(&mut graph, |b| (b.pass(), b.write_buffer(imported)));
+
+    assert_eq!(graph.culled_passes(), vec![
+        (unused, CullReason::NoConsumers),
+        (producer, CullReason::ConsumersCulled),
+        (consumer, CullReason::NoConsumers),
+    ]);
+    assert_eq!(graph.compile().schedule(), &[imported.0, side_effects, export][..]);
+}
+
+#[test]
+fn culling_keeps_passes_with_ordering_dependents() {
+    let mut graph = Graph::new();
+    let first = add_pass(&mut graph, |b| b.pass());
+    let second = add_pass(&mut graph, |b| {
+        b.depends_on(first);
+        b.side_effects(true);
+        b.pass()
+    });
+    assert!(graph.culled_passes().is_empty());
+    assert_eq!(graph.compile().schedule(), &[first, second][..]);
+}
+
+#[test]
+fn ordering_dependencies_alone_schedule_and_keep_the_producer() {
+    let mut graph = Graph::new();
+    let first = add_pass(&mut graph, |b| b.pass());
+    let (p1, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let p2 = add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        b.side_effects(true);
+        b.pass()
+    });
+    let last = add_pass(&mut graph, |b| {
+        b.depends_on(first);
+        b.side_effects(true);
+        b.pass()
+    });
+    assert!(graph.culled_passes().is_empty());
+    for scheduler in &[&InsertionOrder as &dyn Scheduler, &MinimizeMemory, &LatencyHiding] {
+        let order = schedule(&graph, *scheduler);
+        let position = |pass| order.iter().position(|&p| p == pass).unwrap();
+        assert!(position(first) < position(last));
+        assert!(position(p1) < position(p2));
+    }
+}
+
+#[test]
+#[should_panic(expected = "pass 0 can't depend on itself")]
+fn depending_on_itself_panics() {
+    let mut graph = Graph::new();
+    add_pass(&mut graph, |b| {
+        let pass = b.pass();
+        b.depends_on(pass);
+    });
+}
+
+#[test]
+fn structural_hash_is_stable_across_rebuilds() {
+    let (first, _) = chains();
+    let (second, _) = chains();
+    assert_eq!(first.structural_hash(), second.structural_hash());
+}
+
+#[test]
+fn structural_hash_ignores_callbacks() {
+    let build = |callback: Callback| {
+        let mut graph = Graph::new();
+        graph.add_compute_pass(None, pass(callback, |b| b.create_buffer(info(256), None)));
+        graph
+    };
+    assert_eq!(build(noop).structural_hash(), build(fail).structural_hash());
+}
+
+#[test]
+fn structural_hash_changes_with_structure() {
+    let build = |size: u64, ordered: bool| {
+        let mut graph = Graph::new();
+        let first = add_pass(&mut graph, |b| {
+            b.create_buffer(info(size), None);
+            b.pass()
+        });
+        add_pass(&mut graph, |b| if ordered { b.depends_on(first) });
+        graph
+    };
+    let hash = build(256, false).structural_hash();
+    assert_eq!(build(256, false).structural_hash(), hash);
+    assert_ne!(build(512, false).structural_hash(), hash);
+    assert_ne!(build(256, true).structural_hash(), hash);
+}
+
+#[test]
+fn readers_run_before_the_next_version_is_written() {
+    let mut graph = Graph::new();
+    let (p0, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let p1 = add_pass(&mut graph, |b| {
+        b.write_buffer(a);
+        b.pass()
+    });
+    let p2 = add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        b.pass()
+    });
+    for scheduler in &[&InsertionOrder as &dyn Scheduler, &MinimizeMemory, &LatencyHiding] {
+        assert_eq!(schedule(&graph, *scheduler), vec![p0, p2, p1]);
+    }
+}
+
+#[test]
+#[should_panic(expected = "has written over it")]
+fn reading_an_overwritten_version_panics() {
+    let mut graph = Graph::new();
+    let a = add_pass(&mut graph, |b| b.create_buffer(info(256), None));
+    let a1 = add_pass(&mut graph, |b| b.write_buffer(a));
+    add_pass(&mut graph, |b| {
+        b.read_buffer(a);
+        b.read_buffer(a1);
+    });
+    ScheduleGraph::new(&graph);
+}
+
+#[test]
+fn range_reads_skip_other_writes_but_not_later_ones() {
+    let mut graph = Graph::new();
+    let (p0, a) = add_pass(&mut graph, |b| (b.pass(), b.create_buffer(info(256), None)));
+    let (p1, a1) = add_pass(&mut graph, |b| (b.pass(), b.write_buffer_range(a, 0..128)));
+    let p2 = add_pass(&mut graph, |b| {
+        b.read_buffer_range(a1, 128..256);
+        b.pass()
+    });
+    let p3 = add_pass(&mut graph, |b| {
+        b.write_buffer_range(a1, 128..256);
+        b.pass()
+    });
+
+    let dependencies = ScheduleGraph::new(&graph);
+    let index = |pass: PassRef| (pass.0).index();
+    assert_eq!(dependencies.producers(index(p2)), &[index(p0)][..]);
+    assert!(dependencies.producers(index(p3)).contains(&index(p2)));
+    assert!(dependencies.producers(index(p3)).contains(&index(p1)));
+}
+
+#[test]
+fn structures_compare_by_value() {
+    let (first, _) = chains();
+    let (second, _) = chains();
+    let (other, _) = fan();
+    assert_eq!(first.structure(), second.structure());
+    assert_eq!(first.structure().digest(), first.structural_hash());
+    assert_ne!(first.structure(), other.structure());
+}
\ No newline at end of file
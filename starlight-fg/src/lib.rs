@@ -1,7 +1,10 @@
 extern crate gfx_hal;
 extern crate daggy;
+extern crate rayon;
 
 pub mod pass;
+pub mod context;
 pub mod graph;
+pub mod compiled;
 pub mod serial;
 pub use self::graph::*;
\ No newline at end of file
@@ -1,25 +1,47 @@
+use std::error::Error;
+
 use gfx_hal::Backend;
-use std::marker::PhantomData;
 
-pub struct GraphicsContext<B: Backend>(PhantomData<B>);
-pub struct ComputeContext<B: Backend>(PhantomData<B>);
+pub use crate::context::{GraphicsContext, ComputeContext, TransferContext};
+
+// What a pass fails with. The renderer abandons the frame and returns it, along with the failing pass.
+// It's boxed rather than a parameter of the pass traits so that passes failing in different ways can share
+// a graph; `RendererError::pass_error` hands it back for downcasting to the type the pass returned.
+pub type PassError = Box<dyn Error + Send + Sync>;
+
+// Every pass callback is given the user data `D` passed to the renderer for the frame being recorded.
+pub trait GraphicsPass<B: Backend, D = ()>: Send {
+    fn draw(&mut self, context: &mut GraphicsContext<B>, data: &D) -> Result<(), PassError>;
+}
+
+impl<B: Backend, D, T> GraphicsPass<B, D> for T
+    where T: FnMut(&mut GraphicsContext<B>, &D) -> Result<(), PassError> + Send
+{
+    fn draw(&mut self, context: &mut GraphicsContext<B>, data: &D) -> Result<(), PassError> {
+        self(context, data)
+    }
+}
 
-pub trait GraphicsPass<B: Backend> {
-    fn draw(&self, context: &mut GraphicsContext<B>);
+pub trait ComputePass<B: Backend, D = ()>: Send {
+    fn execute(&mut self, context: &mut ComputeContext<B>, data: &D) -> Result<(), PassError>;
 }
 
-impl<B: Backend, T> GraphicsPass<B> for T
-    where T: Fn(&mut GraphicsContext<B>)
+impl<B: Backend, D, T> ComputePass<B, D> for T
+    where T: FnMut(&mut ComputeContext<B>, &D) -> Result<(), PassError> + Send
 {
-    fn draw(&self, context: &mut GraphicsContext<B>) { self(context) }
+    fn execute(&mut self, context: &mut ComputeContext<B>, data: &D) -> Result<(), PassError> {
+        self(context, data)
+    }
 }
 
-pub trait ComputePass<B: Backend> {
-    fn execute(&self, context: &mut ComputeContext<B>);
+pub trait TransferPass<B: Backend, D = ()>: Send {
+    fn execute(&mut self, context: &mut TransferContext<B>, data: &D) -> Result<(), PassError>;
 }
 
-impl<B: Backend, T> ComputePass<B> for T
-    where T: Fn(&mut ComputeContext<B>)
+impl<B: Backend, D, T> TransferPass<B, D> for T
+    where T: FnMut(&mut TransferContext<B>, &D) -> Result<(), PassError> + Send
 {
-    fn execute(&self, context: &mut ComputeContext<B>) { self(context) }
+    fn execute(&mut self, context: &mut TransferContext<B>, data: &D) -> Result<(), PassError> {
+        self(context, data)
+    }
 }
\ No newline at end of file
@@ -1,12 +1,34 @@
-use std::ops::Deref;
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::time::Instant;
 
-use gfx_hal::{Backend, Device, device::OutOfMemory};
+use gfx_hal::{pass as hal_pass, query, Backend, Device, device::OutOfMemory};
 use gfx_hal::pool::{RawCommandPool, CommandPoolCreateFlags};
-use gfx_hal::command::{RawCommandBuffer, RawLevel, SubpassContents};
-use gfx_hal::pso::Rect;
+use gfx_hal::command::{RawCommandBuffer, RawLevel, CommandBufferFlags, CommandBufferInheritanceInfo, SubpassContents};
+use gfx_hal::queue::{RawCommandQueue, Submission};
+use gfx_hal::pso::PipelineStage;
 
-use crate::FrameGraph;
-use crate::pass::{GraphicsPass, ComputePass};
+use crate::compiled::{CompiledFrameGraph, Imports, ResolvedQueues, SyncPoint, queue_stages};
+use crate::context::{GraphicsContext, ComputeContext, TransferContext, Counters};
+
+mod objects;
+mod parallel;
+mod queues;
+mod samplers;
+mod timings;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::parallel::ParallelRenderer;
+pub use self::queues::*;
+pub use self::samplers::SamplerCache;
+pub use self::timings::{FrameTimings, FrameStats, PassStats};
+
+use self::objects::{FrameObjects, ObjectCache};
+use self::timings::{Timestamps, pass_label};
 
 mod graph {
     pub use crate::graph::*;
@@ -15,99 +37,676 @@ mod graph {
 
 use self::graph::*;
 
-enum RenderPassKind<'p, B: Backend> {
-    Graphics(&'p dyn GraphicsPass<B>),
-    Compute(&'p dyn ComputePass<B>),
+struct ExecutorPass {
+    queue: QueueType,
+    // Whether the pass's queue can run graphics commands, which transfer passes need to blit and clear.
+    graphics: bool,
+    pool: usize,
+    buffer: usize,
+    // The pass's secondary command buffers in its pool, if it records into them.
+    secondary: Range<usize>,
+}
+
+struct QueueSemaphore<B: Backend> {
+    handle: B::Semaphore,
+    producer: SyncPoint,
+    consumer: SyncPoint,
+}
+
+struct QueuePool<B: Backend> {
+    handle: B::CommandPool,
+    buffers: Vec<B::CommandBuffer>,
+    secondary: Vec<SecondaryBuffer<B>>,
+}
+
+// Command pools can only be used by one thread at a time, so every secondary buffer gets its own to let
+// passes record them in parallel.
+struct SecondaryBuffer<B: Backend> {
+    pool: B::CommandPool,
+    buffer: B::CommandBuffer,
 }
 
-struct RenderPass<'p, B: Backend> {
-    pass_handle: B::RenderPass,
-    render_area: Rect,
-    first_subpass: SubpassContents,
-    kind: RenderPassKind<'p, B>,
+// Everything a frame still in flight on the GPU may be using, so it can't be shared between frames.
+struct Frame<B: Backend> {
+    // Signalled once every queue is done with the frame.
+    fence: B::Fence,
+    pools: Vec<QueuePool<B>>,
+    // A graphics pool for the commands around the passes, with the buffers submitted before and after them.
+    control: QueuePool<B>,
+    semaphores: Vec<QueueSemaphore<B>>,
+    timestamps: Option<Timestamps<B>>,
 }
 
-impl<'p, B: Backend> RenderPass<'p, B> {
-    fn record(&self, buffer: &mut B::CommandBuffer) {
-        buffer.begin_render_pass(
-            &self.pass_handle,
-            unimplemented!(),
-            self.render_area,
-            unimplemented!(),
-            self.first_subpass,
-        );
+// How command pools are laid out in a frame. Command pools can't be used from several threads at once, so
+// recording passes in parallel needs a pool per pass rather than one per queue.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PoolLayout {
+    PerQueue,
+    PerPass,
+}
+
+// The state shared by the renderers: everything but how the passes of a frame get recorded.
+// Frames are rendered round-robin into as many sets of command buffers and transient resources as the
+// graph was compiled with frames in flight.
+struct Executor<'g, B: Backend> {
+    device: &'g B::Device,
+    // The compiled graph the executor was created for, and the only one it may render.
+    graph: usize,
+    queues: ResolvedQueues,
+    passes: Vec<ExecutorPass>,
+    frames: Vec<Frame<B>>,
+    // The number of frames rendered so far.
+    frame: usize,
+    // Nanoseconds per timestamp tick, while profiling.
+    timestamp_period: Option<f32>,
+    timings: Option<FrameTimings>,
+    stats: FrameStats,
+    samplers: SamplerCache<'g, B>,
+    objects: ObjectCache<B>,
+}
+
+impl<'g, B: Backend> Executor<'g, B> {
+    fn new<D>(
+        device: &'g B::Device,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        queues: &Queues<B>,
+        layout: PoolLayout,
+    ) -> Result<Self, RendererError> {
+        let passes = Self::create_passes(compiled, queues, layout)?;
+        let frames = (0..compiled.frames_in_flight())
+            .map(|_| Self::create_frame(device, compiled, &passes, queues, layout))
+            .collect::<Result<_, _>>()?;
+        Ok(Executor {
+            device: device,
+            graph: compiled.id(),
+            queues: queues.resolved(),
+            passes: passes,
+            frames: frames,
+            frame: 0,
+            timestamp_period: None,
+            timings: None,
+            stats: FrameStats::default(),
+            samplers: SamplerCache::new(device),
+            objects: ObjectCache::new(),
+        })
+    }
+
+    fn current_frame(&self) -> usize {
+        self.frame % self.frames.len()
+    }
+
+    fn enable_profiling(&mut self, timestamp_period: f32) -> Result<(), RendererError> {
+        for frame in &mut self.frames {
+            if frame.timestamps.is_none() {
+                frame.timestamps = Some(Timestamps::new(self.device, self.passes.len())?);
+            }
+        }
+        self.timestamp_period = Some(timestamp_period);
+        Ok(())
+    }
+
+    fn disable_profiling(&mut self) -> Result<(), RendererError> {
+        self.wait_idle()?;
+        for frame in &mut self.frames {
+            if let Some(timestamps) = frame.timestamps.take() {
+                timestamps.destroy(self.device);
+            }
+        }
+        self.timestamp_period = None;
+        self.timings = None;
+        Ok(())
+    }
+
+    // Waits for the GPU to finish the last frame recorded into this frame's resources, then readies them
+    // for recording. Returns the cached objects the frame uses.
+    fn begin_frame<D>(
+        &mut self,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        imports: &Imports<B>,
+    ) -> Result<FrameObjects, RendererError> {
+        assert_eq!(compiled.id(), self.graph, "Renderer used with a graph other than the one it was created for!");
+        let device = self.device;
+        let current = self.frame;
+        let slot = current % self.frames.len();
+        let frame = &mut self.frames[slot];
+        device.wait_for_fence(&frame.fence, !0)?;
+        device.reset_fence(&frame.fence)?;
+        if let (Some(timestamps), Some(period)) = (frame.timestamps.as_mut(), self.timestamp_period) {
+            if let Some(measured) = timestamps.frame.take() {
+                self.timings = Some(timestamps.read(device, compiled, measured, period)?);
+            }
+            timestamps.frame = Some(current);
+        }
+        for pool in &mut frame.pools {
+            pool.handle.reset();
+            for secondary in &mut pool.secondary {
+                secondary.pool.reset();
+            }
+        }
+        frame.control.handle.reset();
+
+        let objects = match self.objects.prepare(device, compiled, current, imports) {
+            Ok(objects) => objects,
+            Err(error) => {
+                self.abort_frame()?;
+                return Err(error)
+            },
+        };
+        let buffers = &mut self.frames[slot].control.buffers;
+        record_begin(&mut buffers[0], compiled, current, imports, &self.queues);
+        record_end(&mut buffers[1], compiled, current, imports, &self.queues);
+        Ok(objects)
+    }
+
+    // Gives up on the frame begun by begin_frame without submitting anything, leaving its resources ready
+    // to record the same frame again.
+    fn abort_frame(&mut self) -> Result<(), RendererError> {
+        let slot = self.frame % self.frames.len();
+        let frame = &mut self.frames[slot];
+        // Nothing will signal the fence that begin_frame reset, so swap it for a signalled one.
+        let fence = mem::replace(&mut frame.fence, self.device.create_fence(true)?);
+        self.device.destroy_fence(fence);
+        if let Some(ref mut timestamps) = frame.timestamps {
+            timestamps.frame = None;
+        }
+        Ok(())
+    }
+
+    // Submits the frame recorded since begin_frame and moves on to the next.
+    fn end_frame(&mut self, queues: &mut Queues<B>, stats: FrameStats) {
+        let slot = self.frame % self.frames.len();
+        Self::submit(&self.frames[slot], &self.passes, queues);
+        self.stats = stats;
+        self.frame += 1;
+    }
+
+    fn wait_idle(&self) -> Result<(), RendererError> {
+        for frame in &self.frames {
+            self.device.wait_for_fence(&frame.fence, !0)?;
+        }
+        Ok(())
+    }
+
+    fn evict_image(&mut self, key: u64) -> Result<(), RendererError> {
+        self.wait_idle()?;
+        self.objects.evict(self.device, key);
+        Ok(())
     }
 }
 
+// Executes a compiled frame graph one pass at a time, recording each pass into its own command buffer.
 pub struct SerialRenderer<'g, B: Backend> {
-    device: &'g B::Device,
-    graph: &'g FrameGraph<'g, B>,
-    graphics_pool: B::CommandPool,
-    graphics_buffers: Vec<B::CommandBuffer>,
-    compute_pool: B::CommandPool,
-    compute_buffers: Vec<B::CommandBuffer>,
-    passes: Vec<RenderPass<'g, B>>,
+    executor: Executor<'g, B>,
 }
 
 impl<'g, B: Backend> SerialRenderer<'g, B> {
-    pub fn new(device: &'g B::Device, graph: &'g FrameGraph<'g, B>) -> Result<Self, OutOfMemory> {
-        let graphics_pool = device.create_command_pool(unimplemented!(), CommandPoolCreateFlags::TRANSIENT)?;
-        let compute_pool = device.create_command_pool(unimplemented!(), CommandPoolCreateFlags::TRANSIENT)?;
-        let (graphics_buffers, compute_buffers) = Self::allocate_command_buffers(graph, compute_pool, graphics_pool);
+    pub fn new<D>(device: &'g B::Device, compiled: &CompiledFrameGraph<'g, B, D>, queues: &Queues<B>) -> Result<Self, RendererError> {
         Ok(SerialRenderer {
-            device: device,
-            graph: graph,
-            graphics_pool: graphics_pool,
-            graphics_buffers: graphics_buffers,
-            compute_pool: compute_pool,
-            compute_buffers: compute_buffers,
-            passes: Self::create_passes(device, graph),
+            executor: Executor::new(device, compiled, queues, PoolLayout::PerQueue)?,
         })
     }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.executor.frames.len()
+    }
+
+    // The frame the next call to render will record, which passes see through their context.
+    pub fn current_frame(&self) -> usize {
+        self.executor.current_frame()
+    }
+
+    // Times every pass on the GPU from the next frame on. `timestamp_period` comes from the device limits.
+    pub fn enable_profiling(&mut self, timestamp_period: f32) -> Result<(), RendererError> {
+        self.executor.enable_profiling(timestamp_period)
+    }
+
+    pub fn disable_profiling(&mut self) -> Result<(), RendererError> {
+        self.executor.disable_profiling()
+    }
+
+    // The timings of the most recent frame the GPU has finished, which lags behind the frame being
+    // rendered by the number of frames in flight.
+    pub fn timings(&self) -> Option<&FrameTimings> {
+        self.executor.timings.as_ref()
+    }
+
+    // What the CPU spent recording the last frame rendered.
+    pub fn stats(&self) -> &FrameStats {
+        &self.executor.stats
+    }
+
+    // The samplers passes get from their contexts, for creating them ahead of the first frame.
+    pub fn samplers(&self) -> &SamplerCache<'g, B> {
+        &self.executor.samplers
+    }
+
+    // Waits for the GPU to finish the last frame recorded into this frame's resources before reusing them.
+    // `data` is passed on to every pass. If a pass fails, nothing is submitted and the next call records
+    // the same frame again.
+    pub fn render<D>(
+        &mut self,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        imports: &Imports<B>,
+        queues: &mut Queues<B>,
+        data: &D,
+    ) -> Result<(), RendererError> {
+        let record_start = Instant::now();
+        let objects = self.executor.begin_frame(compiled, imports)?;
+        let current = self.executor.frame;
+        let slot = current % self.executor.frames.len();
+        let Frame { ref mut pools, ref timestamps, .. } = self.executor.frames[slot];
+        let resolved = &self.executor.queues;
+        let passes = &self.executor.passes;
+        let samplers = &self.executor.samplers;
+        let views = self.executor.objects.import_views(&objects);
+        let framebuffers = self.executor.objects.framebuffers(&objects);
+
+        let recorded = framebuffers.into_iter().enumerate().map(|(position, framebuffer)| {
+            let pass = &passes[position];
+            let pool = &mut pools[pass.pool];
+            record_pass(
+                &mut pool.buffers[pass.buffer],
+                &mut pool.secondary[pass.secondary.clone()],
+                compiled,
+                resolved,
+                passes,
+                position,
+                current,
+                framebuffer,
+                timestamps.as_ref(),
+                imports,
+                &views,
+                samplers,
+                data,
+            )
+        }).collect::<Result<_, _>>();
+        let recorded = match recorded {
+            Ok(recorded) => recorded,
+            Err(error) => {
+                self.executor.abort_frame()?;
+                return Err(error)
+            },
+        };
+
+        let stats = FrameStats {
+            frame: current,
+            record_time: record_start.elapsed(),
+            passes: recorded,
+        };
+        self.executor.end_frame(queues, stats);
+        Ok(())
+    }
+
+    pub fn wait_idle(&self) -> Result<(), RendererError> {
+        self.executor.wait_idle()
+    }
+
+    // Views and framebuffers made from imported images are kept for as long as the images, which are told
+    // apart by the key they were imported with. This destroys those made from the image imported with
+    // `key`, and has to be called before the image itself is destroyed or the key given to another image,
+    // as when a swapchain is recreated. Waits for the GPU to finish with them first.
+    pub fn evict_image(&mut self, key: u64) -> Result<(), RendererError> {
+        self.executor.evict_image(key)
+    }
 }
 
-impl<'g, B: Backend> SerialRenderer<'g, B> {
-    fn allocate_command_buffers(
-        graph: &'g FrameGraph<'g, B>, 
-        graphics_pool: B::CommandPool, 
-        compute_pool: B::CommandPool,
-    ) -> (Vec<B::CommandBuffer>, Vec<B::CommandBuffer>) {
-        let (graphics_buffers, compute_buffers) = graph.passes().fold((0, 0), |(g, c), p| match p.kind {
-            graph::RenderPassKind::Graphics(_) => (g + 1, c),
-            graph::RenderPassKind::Compute(_) => (g, c + 1),
-        });
-        (graphics_pool.allocate(graphics_buffers, RawLevel::Primary), compute_pool.allocate(compute_buffers, RawLevel::Primary))
-    }
-
-    fn create_passes(device: &'g B::Device, graph: &'g FrameGraph<'g, B>) -> Vec<RenderPass<'g, B>> {
-        graph.pass_refs().map(|pass| {
-            match graph.get_pass(pass).kind {
-                graph::RenderPassKind::Graphics(ref pass) => {
-                    RenderPass {
-                        pass_handle: unimplemented!(),
-                        render_area: unimplemented!(),
-                        first_subpass: SubpassContents::Inline,
-                        kind: RenderPassKind::Graphics(pass.deref()),
-                    }
-                },
-                graph::RenderPassKind::Compute(ref pass) => {
-                    RenderPass {
-                        pass_handle: unimplemented!(),
-                        render_area: unimplemented!(),
-                        first_subpass: SubpassContents::Inline,
-                        kind: RenderPassKind::Compute(pass.deref()),
-                    }
+// Records everything a pass does in a frame into its command buffer, from the barriers before it to those
+// releasing resources to other queues after it. Every renderer goes through this, so they all produce the
+// same commands. The buffer is finished even if the pass fails, so it can be reset along with the rest.
+fn record_pass<'g, B: Backend, D>(
+    buffer: &mut B::CommandBuffer,
+    secondary: &mut [SecondaryBuffer<B>],
+    compiled: &CompiledFrameGraph<'g, B, D>,
+    queues: &ResolvedQueues,
+    passes: &[ExecutorPass],
+    position: usize,
+    frame: usize,
+    framebuffer: Option<&B::Framebuffer>,
+    timestamps: Option<&Timestamps<B>>,
+    imports: &Imports<B>,
+    import_views: &[(usize, &B::ImageView)],
+    samplers: &SamplerCache<B>,
+    data: &D,
+) -> Result<PassStats, RendererError> {
+    let compiled_pass = &compiled.passes()[position];
+    let source = compiled.graph().get_pass(compiled_pass.pass);
+    buffer.begin(CommandBufferFlags::ONE_TIME_SUBMIT, CommandBufferInheritanceInfo::default());
+    buffer.begin_debug_marker(&compiled.graph().describe_pass(compiled_pass.pass), marker_color(&source.kind));
+    if let Some(timestamps) = timestamps {
+        let (before, after) = (Timestamps::<B>::before(position), Timestamps::<B>::after(position));
+        buffer.reset_query_pool(&timestamps.pool, before..after + 1);
+        buffer.write_timestamp(PipelineStage::TOP_OF_PIPE, query::Query { pool: &timestamps.pool, id: before });
+    }
+    let mut barriers = compiled_pass.pre_barriers.record(buffer, compiled.resources(), frame, imports, queues);
+
+    let resources = compiled.resource_handles(compiled_pass, frame, imports, import_views, samplers);
+    let (result, cpu_time, counters) = match source.kind {
+        graph::RenderPassKind::Graphics(ref graphics, _) => {
+            let render_pass = compiled_pass.render_pass.as_ref().unwrap();
+            let framebuffer = framebuffer.unwrap();
+            let contents = if secondary.is_empty() { SubpassContents::Inline } else { SubpassContents::SecondaryBuffers };
+            buffer.begin_render_pass(
+                &*render_pass.handle,
+                framebuffer,
+                render_pass.render_area,
+                &render_pass.clear_values,
+                contents,
+            );
+            for secondary in secondary.iter_mut() {
+                secondary.buffer.begin(
+                    CommandBufferFlags::ONE_TIME_SUBMIT | CommandBufferFlags::RENDER_PASS_CONTINUE,
+                    CommandBufferInheritanceInfo {
+                        subpass: Some(hal_pass::Subpass { index: 0, main_pass: &*render_pass.handle }),
+                        framebuffer: Some(framebuffer),
+                        ..CommandBufferInheritanceInfo::default()
+                    },
+                );
+            }
+            let recorded = {
+                let secondary = secondary.iter_mut().map(|secondary| &mut secondary.buffer);
+                let mut context = GraphicsContext::with_secondary(buffer, &resources, secondary);
+                let start = Instant::now();
+                let result = graphics.lock().unwrap().draw(&mut context, data);
+                (result, start.elapsed(), context.counters())
+            };
+            if !secondary.is_empty() {
+                for secondary in secondary.iter_mut() {
+                    secondary.buffer.finish();
                 }
+                buffer.execute_commands(secondary.iter().map(|secondary| &secondary.buffer));
             }
-        }).collect::<Vec<_>>()
+            buffer.end_render_pass();
+            recorded
+        },
+        graph::RenderPassKind::Compute(ref compute) => {
+            let mut context = ComputeContext::new(buffer, &resources);
+            let start = Instant::now();
+            let result = compute.lock().unwrap().execute(&mut context, data);
+            (result, start.elapsed(), context.counters())
+        },
+        graph::RenderPassKind::Transfer(ref transfer) => {
+            let start = Instant::now();
+            let mut context = TransferContext::new(buffer, &resources, passes[position].graphics);
+            let result = transfer.lock().unwrap().execute(&mut context, data);
+            (result, start.elapsed(), Counters::default())
+        },
+        graph::RenderPassKind::Import | graph::RenderPassKind::History => unreachable!(),
+    };
+
+    barriers += compiled_pass.post_barriers.record(buffer, compiled.resources(), frame, imports, queues);
+    if let Some(timestamps) = timestamps {
+        let after = Timestamps::<B>::after(position);
+        buffer.write_timestamp(PipelineStage::BOTTOM_OF_PIPE, query::Query { pool: &timestamps.pool, id: after });
+    }
+    buffer.end_debug_marker();
+    buffer.finish();
+
+    match result {
+        Ok(()) => Ok(PassStats {
+            name: pass_label(compiled.graph(), compiled_pass.pass),
+            cpu_time: cpu_time,
+            draws: counters.draws,
+            dispatches: counters.dispatches,
+            descriptor_binds: counters.descriptor_binds,
+            barriers: barriers,
+        }),
+        Err(error) => Err(RendererError::Pass {
+            pass: compiled.graph().describe_pass(compiled_pass.pass),
+            error: Box::new(RendererError::Execution(error)),
+        }),
     }
 }
 
-impl<'g, B: Backend> Drop for SerialRenderer<'g, B> {
+// Records what the graphics queue does before any pass of a frame: on the first frame, giving history
+// resources the state they are read in before anything was written to them, and then releasing resources
+// to the queues that use them first.
+fn record_begin<'g, B: Backend, D>(
+    buffer: &mut B::CommandBuffer,
+    compiled: &CompiledFrameGraph<'g, B, D>,
+    frame: usize,
+    imports: &Imports<B>,
+    queues: &ResolvedQueues,
+) {
+    buffer.begin(CommandBufferFlags::ONE_TIME_SUBMIT, CommandBufferInheritanceInfo::default());
+    if frame == 0 {
+        compiled.history_barriers().record(buffer, compiled.resources(), frame, imports, queues);
+    }
+    compiled.begin_barriers().record(buffer, compiled.resources(), frame, imports, queues);
+    buffer.finish();
+}
+
+// Records what the graphics queue does once every pass of a frame is done: taking imported and history
+// resources back for the next frame.
+fn record_end<'g, B: Backend, D>(
+    buffer: &mut B::CommandBuffer,
+    compiled: &CompiledFrameGraph<'g, B, D>,
+    frame: usize,
+    imports: &Imports<B>,
+    queues: &ResolvedQueues,
+) {
+    buffer.begin(CommandBufferFlags::ONE_TIME_SUBMIT, CommandBufferInheritanceInfo::default());
+    compiled.end_barriers().record(buffer, compiled.resources(), frame, imports, queues);
+    buffer.finish();
+}
+
+impl<'g, B: Backend> Executor<'g, B> {
+    // Passes are submitted in schedule order, so every semaphore is signalled before anything waits on
+    // it, and the queues are free to run concurrently in between.
+    fn submit(frame: &Frame<B>, passes: &[ExecutorPass], queues: &mut Queues<B>) {
+        let begin = submission(frame, SyncPoint::Begin, QueueType::Graphics, &frame.control.buffers[0]);
+        unsafe { queues.raw_queue(QueueType::Graphics).submit(begin, None) };
+        for (index, pass) in passes.iter().enumerate() {
+            let buffer = &frame.pools[pass.pool].buffers[pass.buffer];
+            let submission = submission(frame, SyncPoint::Pass(index), pass.queue, buffer);
+            unsafe { queues.raw_queue(pass.queue).submit(submission, None) };
+        }
+        // The fence only covers the graphics queue, which waits for the last pass of every other queue here.
+        let end = submission(frame, SyncPoint::End, QueueType::Graphics, &frame.control.buffers[1]);
+        unsafe { queues.raw_queue(QueueType::Graphics).submit(end, Some(&frame.fence)) };
+    }
+
+    fn create_frame<D>(
+        device: &'g B::Device,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        passes: &[ExecutorPass],
+        queues: &Queues<B>,
+        layout: PoolLayout,
+    ) -> Result<Frame<B>, OutOfMemory> {
+        let pools = match layout {
+            PoolLayout::PerQueue => Self::create_pools(device, passes, queues)?,
+            PoolLayout::PerPass => passes.iter()
+                .map(|pass| Self::create_pool(device, pass.queue, 1, pass.secondary.len(), queues))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(Frame {
+            fence: device.create_fence(true)?,
+            pools: pools,
+            control: Self::create_pool(device, QueueType::Graphics, 2, 0, queues)?,
+            semaphores: Self::create_semaphores(device, compiled, passes)?,
+            timestamps: None,
+        })
+    }
+
+    fn create_pools(device: &'g B::Device, passes: &[ExecutorPass], queues: &Queues<B>) -> Result<Vec<QueuePool<B>>, OutOfMemory> {
+        pool_queues(queues)
+            .map(|queue| {
+                let passes = passes.iter().filter(|p| p.queue == queue);
+                let secondary = passes.clone().map(|p| p.secondary.len()).sum();
+                Self::create_pool(device, queue, passes.count(), secondary, queues)
+            })
+            .collect()
+    }
+
+    fn create_pool(
+        device: &'g B::Device,
+        queue: QueueType,
+        buffers: usize,
+        secondary: usize,
+        queues: &Queues<B>,
+    ) -> Result<QueuePool<B>, OutOfMemory> {
+        let family = queues.family(queue);
+        let mut handle = device.create_command_pool(family, CommandPoolCreateFlags::TRANSIENT)?;
+        let secondary = (0..secondary).map(|_| {
+            let mut pool = device.create_command_pool(family, CommandPoolCreateFlags::TRANSIENT)?;
+            Ok(SecondaryBuffer {
+                buffer: pool.allocate(1, RawLevel::Secondary).pop().unwrap(),
+                pool: pool,
+            })
+        }).collect::<Result<_, OutOfMemory>>()?;
+        Ok(QueuePool {
+            buffers: handle.allocate(buffers, RawLevel::Primary),
+            secondary: secondary,
+            handle: handle,
+        })
+    }
+
+    fn create_semaphores<D>(
+        device: &'g B::Device,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        passes: &[ExecutorPass],
+    ) -> Result<Vec<QueueSemaphore<B>>, OutOfMemory> {
+        let position = |pass| compiled.passes().iter().position(|p| p.pass == pass).unwrap();
+        let queue = |point| match point {
+            SyncPoint::Pass(position) => passes[position].queue,
+            SyncPoint::Begin | SyncPoint::End => QueueType::Graphics,
+        };
+        let transfers = compiled.queue_transfers().iter()
+            .map(|transfer| (SyncPoint::Pass(position(transfer.producer)), SyncPoint::Pass(position(transfer.consumer))));
+        let ends = [QueueType::Compute, QueueType::Transfer].iter()
+            .filter_map(|&queue| passes.iter().rposition(|p| p.queue == queue))
+            .map(|last| (SyncPoint::Pass(last), SyncPoint::End));
+        let mut pairs = Vec::new();
+        for pair in transfers.chain(compiled.syncs().iter().cloned()).chain(ends) {
+            if queue(pair.0) != queue(pair.1) && !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+        pairs.into_iter()
+            .map(|(producer, consumer)| {
+                Ok(QueueSemaphore {
+                    handle: device.create_semaphore()?,
+                    producer: producer,
+                    consumer: consumer,
+                })
+            })
+            .collect()
+    }
+
+    fn create_passes<D>(
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        queues: &Queues<B>,
+        layout: PoolLayout,
+    ) -> Result<Vec<ExecutorPass>, RendererError> {
+        let mut buffers = HashMap::new();
+        compiled.passes().iter().enumerate().map(|(position, compiled_pass)| {
+            let pass = compiled_pass.pass;
+            let source = compiled.graph().get_pass(pass);
+            let queue = queues.resolve(source.queue);
+            let family = queues.family(queue);
+            // Transfer passes that blit or clear already ask for the graphics queue.
+            let capability = match source.kind {
+                graph::RenderPassKind::Graphics(..) => QueueType::Graphics,
+                graph::RenderPassKind::Compute(_) => QueueType::Compute,
+                _ => source.queue,
+            };
+            if !queues.supports(family, capability) {
+                return Err(RendererError::MissingCapability {
+                    pass: pass,
+                    name: compiled.graph().describe_pass(pass),
+                    family: family,
+                    capability: match capability {
+                        QueueType::Graphics => "graphics",
+                        QueueType::Compute => "compute",
+                        QueueType::Transfer => "transfer",
+                    },
+                })
+            }
+
+            let secondary_buffers = match source.kind {
+                graph::RenderPassKind::Graphics(_, count) => count,
+                _ => 0,
+            };
+            let (pool, buffer, secondary) = match layout {
+                PoolLayout::PerQueue => {
+                    let pool = pool_queues(queues).position(|q| q == queue).unwrap();
+                    let &mut (ref mut count, ref mut secondary) = buffers.entry(queue).or_insert((0, 0));
+                    *count += 1;
+                    *secondary += secondary_buffers;
+                    (pool, *count - 1, *secondary - secondary_buffers..*secondary)
+                },
+                PoolLayout::PerPass => (position, 0, 0..secondary_buffers),
+            };
+            Ok(ExecutorPass {
+                queue: queue,
+                graphics: queues.supports(family, QueueType::Graphics),
+                pool: pool,
+                buffer: buffer,
+                secondary: secondary,
+            })
+        }).collect()
+    }
+}
+
+// The submission at `point` on `queue`, waiting for and signalling the semaphores that link it to other
+// queues. Waits hold back every stage, as whatever crosses queues can be used anywhere in the submission.
+fn submission<'a, B: Backend>(
+    frame: &'a Frame<B>,
+    point: SyncPoint,
+    queue: QueueType,
+    buffer: &'a B::CommandBuffer,
+) -> Submission<
+    Option<&'a B::CommandBuffer>,
+    impl Iterator<Item=(&'a B::Semaphore, PipelineStage)> + 'a,
+    impl Iterator<Item=&'a B::Semaphore> + 'a,
+> {
+    Submission {
+        command_buffers: Some(buffer),
+        wait_semaphores: frame.semaphores.iter()
+            .filter(move |s| s.consumer == point)
+            .map(move |s| (&s.handle, queue_stages(queue))),
+        signal_semaphores: frame.semaphores.iter()
+            .filter(move |s| s.producer == point)
+            .map(|s| &s.handle),
+    }
+}
+
+// The queues that get a command pool of their own when pools are per queue, in pool order.
+fn pool_queues<'a, B: Backend>(queues: &'a Queues<B>) -> impl Iterator<Item=QueueType> + 'a {
+    [QueueType::Graphics, QueueType::Compute, QueueType::Transfer].iter()
+        .cloned()
+        .filter(move |&queue| queues.resolve(queue) == queue)
+}
+
+// Colors passes in captures by kind, packed as 0xRRGGBBAA.
+fn marker_color<B: Backend, D>(kind: &graph::RenderPassKind<B, D>) -> u32 {
+    match *kind {
+        graph::RenderPassKind::Graphics(..) => 0x4080FFFF,
+        graph::RenderPassKind::Compute(_) => 0x40C040FF,
+        graph::RenderPassKind::Transfer(_) => 0xE0C040FF,
+        graph::RenderPassKind::Import | graph::RenderPassKind::History => 0x808080FF,
+    }
+}
+
+impl<'g, B: Backend> Drop for Executor<'g, B> {
     fn drop(&mut self) {
-        self.graphics_pool.free(self.graphics_buffers);
-        self.device.destroy_command_pool(self.graphics_pool);
-        self.compute_pool.free(self.compute_buffers);
-        self.device.destroy_command_pool(self.compute_pool);
+        for mut frame in self.frames.drain(..) {
+            let _ = self.device.wait_for_fence(&frame.fence, !0);
+            for semaphore in frame.semaphores {
+                self.device.destroy_semaphore(semaphore.handle);
+            }
+            for mut pool in frame.pools.into_iter().chain(iter::once(frame.control)) {
+                pool.handle.free(pool.buffers);
+                for mut secondary in pool.secondary {
+                    secondary.pool.free(vec![secondary.buffer]);
+                    self.device.destroy_command_pool(secondary.pool);
+                }
+                self.device.destroy_command_pool(pool.handle);
+            }
+            if let Some(timestamps) = frame.timestamps {
+                timestamps.destroy(self.device);
+            }
+            self.device.destroy_fence(frame.fence);
+        }
+        self.objects.destroy(self.device);
     }
 }
\ No newline at end of file
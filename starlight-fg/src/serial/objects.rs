@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+
+use gfx_hal::{image, Backend, Device};
+
+use crate::compiled::{CompiledFrameGraph, Imports, view_kind};
+use crate::serial::RendererError;
+
+// A view a framebuffer was made from: one the compiled graph made of a copy of a transient image, or one
+// made here of an imported image, which is told apart by the key it was imported with. Renderers only ever
+// use the compiled graph they were created for, so its view indices always mean the same views.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum ViewId {
+    Compiled(usize, usize),
+    Imported(usize, u64),
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub(crate) struct FramebufferKey {
+    pass: usize,
+    views: Vec<ViewId>,
+    extent: image::Extent,
+}
+
+// What a frame uses from the cache: the key of the image behind each view of an imported image, and the
+// framebuffer of every pass that has a render pass.
+pub(crate) struct FrameObjects {
+    pub imports: Vec<(usize, u64)>,
+    pub framebuffers: Vec<Option<FramebufferKey>>,
+}
+
+// Views of imported images and framebuffers, kept from frame to frame for as long as the images they are
+// made from, so they are only created the first time a frame uses them.
+pub(crate) struct ObjectCache<B: Backend> {
+    views: HashMap<(usize, u64), B::ImageView>,
+    framebuffers: HashMap<FramebufferKey, B::Framebuffer>,
+}
+
+impl<B: Backend> ObjectCache<B> {
+    pub(crate) fn new() -> Self {
+        ObjectCache {
+            views: HashMap::new(),
+            framebuffers: HashMap::new(),
+        }
+    }
+
+    // Creates whatever `frame` needs that isn't cached yet.
+    pub(crate) fn prepare<D>(
+        &mut self,
+        device: &B::Device,
+        compiled: &CompiledFrameGraph<B, D>,
+        frame: usize,
+        imports: &Imports<B>,
+    ) -> Result<FrameObjects, RendererError> {
+        let resources = compiled.resources();
+        let mut imported = Vec::new();
+        for index in (0..resources.view_count()).filter(|&view| resources.is_imported_view(view)) {
+            let view = resources.view(index);
+            let handle = resources.get_image(view.image, frame, imports);
+            let key = imports.image_key(resources.physical_image(view.image).root);
+            if !self.views.contains_key(&(index, key)) {
+                let created = device.create_image_view(
+                    handle,
+                    view_kind(resources.physical_image(view.image).info.kind, &view.key.range.layers),
+                    view.key.format,
+                    view.key.swizzle,
+                    view.key.range.clone(),
+                )?;
+                self.views.insert((index, key), created);
+            }
+            imported.push((index, key));
+        }
+
+        let mut framebuffers = Vec::with_capacity(compiled.passes().len());
+        for (position, compiled_pass) in compiled.passes().iter().enumerate() {
+            let render_pass = match compiled_pass.render_pass {
+                Some(ref render_pass) => render_pass,
+                None => {
+                    framebuffers.push(None);
+                    continue
+                },
+            };
+            let views = render_pass.attachments.iter().map(|attachment| {
+                match resources.view_copy(attachment.view, frame) {
+                    Some(copy) => ViewId::Compiled(attachment.view, copy),
+                    None => {
+                        let &(_, key) = imported.iter().find(|&&(view, _)| view == attachment.view).unwrap();
+                        ViewId::Imported(attachment.view, key)
+                    },
+                }
+            }).collect::<Vec<_>>();
+            let key = FramebufferKey { pass: position, views: views, extent: render_pass.extent };
+
+            if !self.framebuffers.contains_key(&key) {
+                let handles = render_pass.attachments.iter().zip(&key.views).map(|(attachment, &id)| match id {
+                    ViewId::Compiled(..) => resources.get_view(attachment.view, frame).unwrap(),
+                    ViewId::Imported(view, key) => &self.views[&(view, key)],
+                });
+                let framebuffer = device.create_framebuffer(&render_pass.handle, handles, render_pass.extent)
+                    .map_err(|error| RendererError::Pass {
+                        pass: compiled.graph().describe_pass(compiled_pass.pass),
+                        error: Box::new(error.into()),
+                    })?;
+                self.framebuffers.insert(key.clone(), framebuffer);
+            }
+            framebuffers.push(Some(key));
+        }
+
+        Ok(FrameObjects {
+            imports: imported,
+            framebuffers: framebuffers,
+        })
+    }
+
+    pub(crate) fn import_views(&self, objects: &FrameObjects) -> Vec<(usize, &B::ImageView)> {
+        objects.imports.iter().map(|&(view, key)| (view, &self.views[&(view, key)])).collect()
+    }
+
+    pub(crate) fn framebuffers(&self, objects: &FrameObjects) -> Vec<Option<&B::Framebuffer>> {
+        objects.framebuffers.iter().map(|key| key.as_ref().map(|key| &self.framebuffers[key])).collect()
+    }
+
+    // Destroys everything made from the image imported with `image_key`. Nothing recorded with them may
+    // still be running.
+    pub(crate) fn evict(&mut self, device: &B::Device, image_key: u64) {
+        let views = self.views.keys().filter(|&&(_, key)| key == image_key).cloned().collect::<Vec<_>>();
+        let framebuffers = self.framebuffers.keys()
+            .filter(|framebuffer| framebuffer.views.iter().any(|&id| match id {
+                ViewId::Imported(_, key) => key == image_key,
+                ViewId::Compiled(..) => false,
+            }))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in framebuffers {
+            device.destroy_framebuffer(self.framebuffers.remove(&key).unwrap());
+        }
+        for key in views {
+            device.destroy_image_view(self.views.remove(&key).unwrap());
+        }
+    }
+
+    pub(crate) fn destroy(&mut self, device: &B::Device) {
+        for (_, framebuffer) in self.framebuffers.drain() {
+            device.destroy_framebuffer(framebuffer);
+        }
+        for (_, view) in self.views.drain() {
+            device.destroy_image_view(view);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,130 @@
+use std::time::Instant;
+
+use gfx_hal::Backend;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::prelude::*;
+
+use crate::compiled::{CompiledFrameGraph, Imports};
+use crate::serial::{Executor, Frame, PoolLayout, Queues, RendererError, FrameTimings, FrameStats, SamplerCache, record_pass};
+
+// Executes a compiled frame graph like the serial renderer, but records the passes of a frame on a pool of
+// threads. Every pass has a command pool of its own, so no two threads ever share one, and the buffers
+// are still submitted in schedule order once they have all been recorded.
+pub struct ParallelRenderer<'g, B: Backend> {
+    executor: Executor<'g, B>,
+    threads: ThreadPool,
+}
+
+impl<'g, B: Backend> ParallelRenderer<'g, B> {
+    // Records on `threads` threads, or one per CPU if it is 0.
+    pub fn new<D>(
+        device: &'g B::Device,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        queues: &Queues<B>,
+        threads: usize,
+    ) -> Result<Self, RendererError> {
+        Ok(ParallelRenderer {
+            executor: Executor::new(device, compiled, queues, PoolLayout::PerPass)?,
+            threads: ThreadPoolBuilder::new().num_threads(threads).build()?,
+        })
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.executor.frames.len()
+    }
+
+    pub fn current_frame(&self) -> usize {
+        self.executor.current_frame()
+    }
+
+    pub fn enable_profiling(&mut self, timestamp_period: f32) -> Result<(), RendererError> {
+        self.executor.enable_profiling(timestamp_period)
+    }
+
+    pub fn disable_profiling(&mut self) -> Result<(), RendererError> {
+        self.executor.disable_profiling()
+    }
+
+    pub fn timings(&self) -> Option<&FrameTimings> {
+        self.executor.timings.as_ref()
+    }
+
+    // The CPU time of each pass is measured on the thread that recorded it, so they can add up to more
+    // than the time spent recording the frame.
+    pub fn stats(&self) -> &FrameStats {
+        &self.executor.stats
+    }
+
+    pub fn samplers(&self) -> &SamplerCache<'g, B> {
+        &self.executor.samplers
+    }
+
+    // `data` is shared between the threads recording the passes. If a pass fails, nothing is submitted and
+    // the next call records the same frame again.
+    pub fn render<D: Sync>(
+        &mut self,
+        compiled: &CompiledFrameGraph<'g, B, D>,
+        imports: &Imports<B>,
+        queues: &mut Queues<B>,
+        data: &D,
+    ) -> Result<(), RendererError> {
+        let record_start = Instant::now();
+        let objects = self.executor.begin_frame(compiled, imports)?;
+        let current = self.executor.frame;
+        let slot = current % self.executor.frames.len();
+        let Frame { ref mut pools, ref timestamps, .. } = self.executor.frames[slot];
+        let resolved = &self.executor.queues;
+        let passes = &self.executor.passes;
+        let samplers = &self.executor.samplers;
+        let views = self.executor.objects.import_views(&objects);
+        let framebuffers = self.executor.objects.framebuffers(&objects);
+
+        let recorded: Vec<_> = self.threads.install(|| {
+            pools.par_iter_mut()
+                .zip(framebuffers)
+                .enumerate()
+                .map(|(position, (pool, framebuffer))| record_pass(
+                    &mut pool.buffers[0],
+                    &mut pool.secondary,
+                    compiled,
+                    resolved,
+                    passes,
+                    position,
+                    current,
+                    framebuffer,
+                    timestamps.as_ref(),
+                    imports,
+                    &views,
+                    samplers,
+                    data,
+                ))
+                .collect()
+        });
+
+        // Every pass is recorded even if one fails, but the error reported is the first in schedule order.
+        let recorded = match recorded.into_iter().collect::<Result<Vec<_>, _>>() {
+            Ok(recorded) => recorded,
+            Err(error) => {
+                self.executor.abort_frame()?;
+                return Err(error)
+            },
+        };
+
+        let stats = FrameStats {
+            frame: current,
+            record_time: record_start.elapsed(),
+            passes: recorded,
+        };
+        self.executor.end_frame(queues, stats);
+        Ok(())
+    }
+
+    pub fn wait_idle(&self) -> Result<(), RendererError> {
+        self.executor.wait_idle()
+    }
+
+    // See `SerialRenderer::evict_image`.
+    pub fn evict_image(&mut self, key: u64) -> Result<(), RendererError> {
+        self.executor.evict_image(key)
+    }
+}
\ No newline at end of file
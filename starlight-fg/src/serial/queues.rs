@@ -0,0 +1,178 @@
+use std::error::Error;
+use std::fmt;
+
+use gfx_hal::{image, query, Backend, device::{AllocationError, DeviceLost, OomOrDeviceLost, OutOfMemory}};
+use gfx_hal::queue::{QueueFamily, QueueFamilyId, QueueGroup, Graphics, Compute, Transfer};
+use rayon::ThreadPoolBuildError;
+
+use crate::compiled::ResolvedQueues;
+use crate::graph::{PassRef, QueueType};
+use crate::pass::PassError;
+
+pub struct Queues<'q, B: Backend> {
+    pub families: &'q [B::QueueFamily],
+    pub graphics: &'q mut QueueGroup<B, Graphics>,
+    pub compute: Option<&'q mut QueueGroup<B, Compute>>,
+    pub transfer: Option<&'q mut QueueGroup<B, Transfer>>,
+}
+
+impl<'q, B: Backend> Queues<'q, B> {
+    // Passes that ask for a queue the caller did not provide, or whose family can't do what the queue is
+    // asked for, fall back to the graphics queue.
+    pub fn resolve(&self, queue: QueueType) -> QueueType {
+        let supported = |family| self.supports(family, queue);
+        match queue {
+            QueueType::Compute if self.compute.as_ref().map_or(false, |group| supported(group.family())) => {
+                QueueType::Compute
+            },
+            QueueType::Transfer if self.transfer.as_ref().map_or(false, |group| supported(group.family())) => {
+                QueueType::Transfer
+            },
+            _ => QueueType::Graphics,
+        }
+    }
+
+    pub fn family(&self, queue: QueueType) -> QueueFamilyId {
+        match self.resolve(queue) {
+            QueueType::Graphics => self.graphics.family(),
+            QueueType::Compute => self.compute.as_ref().unwrap().family(),
+            QueueType::Transfer => self.transfer.as_ref().unwrap().family(),
+        }
+    }
+
+    pub(crate) fn raw_queue(&mut self, queue: QueueType) -> &mut B::CommandQueue {
+        match self.resolve(queue) {
+            QueueType::Graphics => self.graphics.queues[0].as_raw_mut(),
+            QueueType::Compute => self.compute.as_mut().unwrap().queues[0].as_raw_mut(),
+            QueueType::Transfer => self.transfer.as_mut().unwrap().queues[0].as_raw_mut(),
+        }
+    }
+
+    pub(crate) fn resolved(&self) -> ResolvedQueues {
+        let resolve = |queue| (self.resolve(queue), self.family(queue));
+        ResolvedQueues {
+            graphics: resolve(QueueType::Graphics),
+            compute: resolve(QueueType::Compute),
+            transfer: resolve(QueueType::Transfer),
+        }
+    }
+
+    // Whether `family` can run the commands of passes meant for `queue`.
+    pub(crate) fn supports(&self, family: QueueFamilyId, queue: QueueType) -> bool {
+        self.families.iter()
+            .find(|f| f.id() == family)
+            .map_or(false, |f| match queue {
+                QueueType::Graphics => f.supports_graphics(),
+                QueueType::Compute => f.supports_compute(),
+                QueueType::Transfer => f.supports_transfer(),
+            })
+    }
+}
+
+#[derive(Debug)]
+pub enum RendererError {
+    OutOfMemory(OutOfMemory),
+    DeviceLost(DeviceLost),
+    ViewCreation(image::ViewError),
+    SamplerCreation(AllocationError),
+    QueryCreation(query::CreationError),
+    ThreadPool(ThreadPoolBuildError),
+    Execution(PassError),
+    MissingCapability {
+        pass: PassRef,
+        name: String,
+        family: QueueFamilyId,
+        capability: &'static str,
+    },
+    Pass {
+        pass: String,
+        error: Box<RendererError>,
+    },
+}
+
+impl RendererError {
+    // The error returned by the pass that failed, if a pass failed.
+    pub fn pass_error(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        match *self {
+            RendererError::Execution(ref error) => Some(&**error),
+            RendererError::Pass { ref error, .. } => error.pass_error(),
+            _ => None,
+        }
+    }
+
+    pub fn into_pass_error(self) -> Option<PassError> {
+        match self {
+            RendererError::Execution(error) => Some(error),
+            RendererError::Pass { error, .. } => error.into_pass_error(),
+            _ => None,
+        }
+    }
+}
+
+impl From<OutOfMemory> for RendererError {
+    fn from(error: OutOfMemory) -> Self {
+        RendererError::OutOfMemory(error)
+    }
+}
+
+impl From<OomOrDeviceLost> for RendererError {
+    fn from(error: OomOrDeviceLost) -> Self {
+        match error {
+            OomOrDeviceLost::OutOfMemory(error) => RendererError::OutOfMemory(error),
+            OomOrDeviceLost::DeviceLost(error) => RendererError::DeviceLost(error),
+        }
+    }
+}
+
+impl From<image::ViewError> for RendererError {
+    fn from(error: image::ViewError) -> Self {
+        RendererError::ViewCreation(error)
+    }
+}
+
+impl From<AllocationError> for RendererError {
+    fn from(error: AllocationError) -> Self {
+        RendererError::SamplerCreation(error)
+    }
+}
+
+impl From<query::CreationError> for RendererError {
+    fn from(error: query::CreationError) -> Self {
+        RendererError::QueryCreation(error)
+    }
+}
+
+impl From<ThreadPoolBuildError> for RendererError {
+    fn from(error: ThreadPoolBuildError) -> Self {
+        RendererError::ThreadPool(error)
+    }
+}
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendererError::OutOfMemory(ref error) => write!(f, "Out of memory: {:?}", error),
+            RendererError::DeviceLost(ref error) => write!(f, "Device lost: {:?}", error),
+            RendererError::ViewCreation(ref error) => write!(f, "Failed to create attachment view: {:?}", error),
+            RendererError::SamplerCreation(ref error) => write!(f, "Failed to create sampler: {:?}", error),
+            RendererError::QueryCreation(ref error) => write!(f, "Failed to create timestamp queries: {:?}", error),
+            RendererError::Execution(ref error) => write!(f, "{}", error),
+            RendererError::ThreadPool(ref error) => write!(f, "Failed to create recording threads: {}", error),
+            RendererError::MissingCapability { ref name, family, capability, .. } => write!(
+                f, "{} requires {} support, but queue family {:?} does not provide it",
+                name, capability, family
+            ),
+            RendererError::Pass { ref pass, ref error } => write!(f, "{}: {}", pass, error),
+        }
+    }
+}
+
+impl Error for RendererError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RendererError::Execution(ref error) => Some(&**error),
+            RendererError::Pass { ref error, .. } => Some(&**error),
+            _ => None,
+        }
+    }
+}
\ No newline at end of file
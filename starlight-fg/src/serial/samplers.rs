@@ -0,0 +1,46 @@
+use std::sync::{Arc, Mutex};
+
+use gfx_hal::{image, Backend, Device};
+
+use crate::serial::RendererError;
+
+// Samplers shared by every pass a renderer records, each created the first time a pass asks for it and
+// kept until the renderer is dropped. Passes can ask for them from any thread.
+pub struct SamplerCache<'g, B: Backend> {
+    device: &'g B::Device,
+    samplers: Mutex<Vec<(image::SamplerInfo, Arc<B::Sampler>)>>,
+}
+
+impl<'g, B: Backend> SamplerCache<'g, B> {
+    pub(crate) fn new(device: &'g B::Device) -> Self {
+        SamplerCache {
+            device: device,
+            samplers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn get(&self, info: &image::SamplerInfo) -> Result<Arc<B::Sampler>, RendererError> {
+        let mut samplers = self.samplers.lock().unwrap();
+        if let Some(&(_, ref sampler)) = samplers.iter().find(|&&(ref i, _)| i == info) {
+            return Ok(sampler.clone())
+        }
+        let sampler = Arc::new(self.device.create_sampler(info.clone())?);
+        samplers.push((info.clone(), sampler.clone()));
+        Ok(sampler)
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.lock().unwrap().len()
+    }
+}
+
+// Samplers still held outside the cache can't be destroyed, and are left to whoever holds them.
+impl<'g, B: Backend> Drop for SamplerCache<'g, B> {
+    fn drop(&mut self) {
+        for (_, sampler) in self.samplers.get_mut().unwrap().drain(..) {
+            if let Ok(sampler) = Arc::try_unwrap(sampler) {
+                self.device.destroy_sampler(sampler);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,36 @@
+use std::fmt;
+use std::time::Duration;
+
+use crate::serial::RendererError;
+use crate::serial::timings::pass_durations;
+
+fn bytes(ticks: &[u64]) -> Vec<u8> {
+    ticks.iter().flat_map(|tick| tick.to_ne_bytes().to_vec()).collect()
+}
+
+#[test]
+fn durations_scale_ticks_by_period() {
+    let data = bytes(&[100, 250, 1000, 1004]);
+    assert_eq!(pass_durations(&data, 2.0), vec![Duration::from_nanos(300), Duration::from_nanos(8)]);
+    assert_eq!(pass_durations(&data, 0.5), vec![Duration::from_nanos(75), Duration::from_nanos(2)]);
+}
+
+#[test]
+fn durations_of_backwards_timestamps_are_zero() {
+    assert_eq!(pass_durations(&bytes(&[500, 400]), 1.0), vec![Duration::from_nanos(0)]);
+}
+
+#[test]
+fn durations_are_empty_without_passes() {
+    assert!(pass_durations(&[], 1.0).is_empty());
+}
+
+#[test]
+fn pass_errors_can_be_downcast() {
+    let error = RendererError::Pass {
+        pass: "pass 0".to_string(),
+        error: Box::new(RendererError::Execution(Box::new(fmt::Error))),
+    };
+    assert!(error.pass_error().unwrap().downcast_ref::<fmt::Error>().is_some());
+    assert!(error.into_pass_error().unwrap().downcast::<fmt::Error>().is_ok());
+}
\ No newline at end of file
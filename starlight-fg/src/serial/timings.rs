@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use gfx_hal::{query, Backend, Device};
+
+use crate::compiled::CompiledFrameGraph;
+use crate::graph::{FrameGraph, PassRef, internal::FrameGraphInternals};
+use crate::serial::RendererError;
+
+// How long the GPU spent on each pass of a frame, in schedule order. Passes without a name are listed
+// under their description, such as "pass 3".
+#[derive(Clone, Debug, Default)]
+pub struct FrameTimings {
+    frame: usize,
+    passes: Vec<(String, Duration)>,
+}
+
+impl FrameTimings {
+    // The frame these timings were measured on, counting from the first frame rendered.
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    pub fn get(&self, pass: &str) -> Option<Duration> {
+        self.passes.iter().find(|&&(ref name, _)| name == pass).map(|&(_, duration)| duration)
+    }
+
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item=(&'a str, Duration)> + 'a {
+        self.passes.iter().map(|&(ref name, duration)| (name.as_str(), duration))
+    }
+
+    pub fn total(&self) -> Duration {
+        self.passes.iter().fold(Duration::new(0, 0), |total, &(_, duration)| total + duration)
+    }
+}
+
+// What the CPU spent recording a pass. `cpu_time` only covers the pass's own callback, and `barriers`
+// counts every image and buffer barrier recorded around it.
+#[derive(Clone, Debug, Default)]
+pub struct PassStats {
+    pub name: String,
+    pub cpu_time: Duration,
+    pub draws: usize,
+    pub dispatches: usize,
+    pub descriptor_binds: usize,
+    pub barriers: usize,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+    pub(crate) frame: usize,
+    pub(crate) record_time: Duration,
+    pub(crate) passes: Vec<PassStats>,
+}
+
+impl FrameStats {
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    // The time taken to record the whole frame, including barriers and framebuffer creation.
+    pub fn record_time(&self) -> Duration {
+        self.record_time
+    }
+
+    pub fn passes(&self) -> &[PassStats] {
+        &self.passes
+    }
+
+    pub fn get(&self, pass: &str) -> Option<&PassStats> {
+        self.passes.iter().find(|stats| stats.name == pass)
+    }
+
+    pub fn draws(&self) -> usize {
+        self.passes.iter().map(|stats| stats.draws).sum()
+    }
+
+    pub fn dispatches(&self) -> usize {
+        self.passes.iter().map(|stats| stats.dispatches).sum()
+    }
+
+    pub fn descriptor_binds(&self) -> usize {
+        self.passes.iter().map(|stats| stats.descriptor_binds).sum()
+    }
+
+    pub fn barriers(&self) -> usize {
+        self.passes.iter().map(|stats| stats.barriers).sum()
+    }
+}
+
+// A timestamp is written before and after every pass of a frame in flight. They are read back once the
+// frame's fence has signalled, so reading them never stalls.
+pub(crate) struct Timestamps<B: Backend> {
+    pub pool: B::QueryPool,
+    // The frame whose timestamps are waiting in the pool, if any.
+    pub frame: Option<usize>,
+}
+
+impl<B: Backend> Timestamps<B> {
+    pub(crate) fn new(device: &B::Device, passes: usize) -> Result<Self, RendererError> {
+        Ok(Timestamps {
+            pool: device.create_query_pool(query::Type::Timestamp, 2 * passes as query::Id)?,
+            frame: None,
+        })
+    }
+
+    pub(crate) fn before(pass: usize) -> query::Id {
+        2 * pass as query::Id
+    }
+
+    pub(crate) fn after(pass: usize) -> query::Id {
+        2 * pass as query::Id + 1
+    }
+
+    // `period` is the number of nanoseconds per timestamp tick, from the device limits.
+    pub(crate) fn read<D>(
+        &self,
+        device: &B::Device,
+        compiled: &CompiledFrameGraph<B, D>,
+        frame: usize,
+        period: f32,
+    ) -> Result<FrameTimings, RendererError> {
+        let count = 2 * compiled.passes().len();
+        let mut data = vec![0u8; 8 * count];
+        device.get_query_pool_results(&self.pool, 0..count as query::Id, &mut data, 8, query::ResultFlags::BITS_64)?;
+
+        let graph = compiled.graph();
+        Ok(FrameTimings {
+            frame: frame,
+            passes: compiled.passes().iter().zip(pass_durations(&data, period)).map(|(pass, duration)| {
+                (pass_label(graph, pass.pass), duration)
+            }).collect(),
+        })
+    }
+
+    pub(crate) fn destroy(self, device: &B::Device) {
+        device.destroy_query_pool(self.pool);
+    }
+}
+
+// Turns the 64 bit timestamps read back from a pool, laid out as `before` and `after` number them, into
+// the time each pass took. A pass whose timestamps run backwards counts as taking no time.
+pub(crate) fn pass_durations(data: &[u8], period: f32) -> Vec<Duration> {
+    let ticks = data.chunks(8).map(|chunk| {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(chunk);
+        u64::from_ne_bytes(bytes)
+    }).collect::<Vec<_>>();
+    ticks.chunks(2).map(|pair| {
+        let elapsed = pair[1].saturating_sub(pair[0]);
+        Duration::from_nanos((elapsed as f64 * period as f64) as u64)
+    }).collect()
+}
+
+// Timings and statistics are keyed by pass name, or by description for passes without one.
+pub(crate) fn pass_label<B: Backend, D>(graph: &FrameGraph<B, D>, pass: PassRef) -> String {
+    graph.pass_name(pass).map(String::from).unwrap_or_else(|| graph.describe_pass(pass))
+}
\ No newline at end of file